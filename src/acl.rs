@@ -0,0 +1,213 @@
+//! Per-client authorization rules
+//!
+//! Without any rules every client may do everything. As soon as one rule is
+//! configured, a request is allowed only if some rule matching the client
+//! permits the operation on the requested path.
+
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+use crate::protocol::Request;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Put,
+    Get,
+    List,
+    Status,
+    Delete,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Operation::Put => "put",
+            Operation::Get => "get",
+            Operation::List => "list",
+            Operation::Status => "status",
+            Operation::Delete => "delete",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AclRule {
+    /// Who the rule applies to: `cn:<name>`, `fingerprint:sha256:<hex>`,
+    /// `token:<name>`, `net:<cidr>` or `*`
+    pub client: String,
+    /// Permitted operations
+    pub allow: Vec<Operation>,
+    /// Path prefixes relative to root; empty means everywhere
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+}
+
+/// What we know about the client making a request
+#[derive(Debug, Clone, Copy)]
+pub struct Subject<'a> {
+    pub ip: IpAddr,
+    /// Certificate CN/SAN
+    pub name: Option<&'a str>,
+    pub fingerprint: Option<&'a str>,
+    /// Name of the pre-shared token the client authenticated with
+    pub token: Option<&'a str>,
+}
+
+impl Subject<'_> {
+    fn describe(&self) -> String {
+        match (self.name, self.token) {
+            (Some(name), _) => format!("{} ({})", name, self.ip),
+            (None, Some(token)) => format!("token:{} ({})", token, self.ip),
+            (None, None) => self.ip.to_string(),
+        }
+    }
+}
+
+/// Operations needed, each with the index into `request.paths()` it applies
+/// to; a request without paths needs its single operation anywhere (and
+/// authentication itself needs none)
+pub fn operations(request: &Request) -> Vec<(usize, Operation)> {
+    let op = match request {
+        Request::Put { .. } | Request::Resume { .. } => Operation::Put,
+        // Whether a file rebuilt from blocks of the existing one is accepted
+        // tells the client what those blocks contain, so it needs read access too
+        Request::Delta { .. } => return vec![(0, Operation::Put), (0, Operation::Get)],
+        // Block signatures reveal content, so they need read access
+        Request::Signature { .. } | Request::Get { .. } => Operation::Get,
+        Request::List { .. } | Request::Manifest { hash: false, .. } => Operation::List,
+        // Hashes identify content, so they need read access like signatures
+        Request::Manifest { hash: true, .. } => Operation::Get,
+        Request::Delete { .. } => Operation::Delete,
+        Request::Mkdir { .. } | Request::Restore { .. } => Operation::Put,
+        Request::Versions { .. } => Operation::List,
+        // Takes the entry away from one place and puts it in another
+        Request::Rename { .. } => return vec![(0, Operation::Delete), (1, Operation::Put)],
+        Request::Status => Operation::Status,
+        Request::Auth { .. } => return Vec::new(),
+    };
+    vec![(0, op)]
+}
+
+/// Check `op` on the root-relative `paths` against `rules`
+pub fn check(rules: &[AclRule], subject: &Subject, op: Operation, paths: &[&str]) -> Result<(), String> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    let matching: Vec<&AclRule> = rules.iter().filter(|r| matches_client(&r.client, subject)).collect();
+    if matching.is_empty() {
+        return Err(format!("Access denied: no ACL rule for {}", subject.describe()));
+    }
+
+    for path in paths {
+        let allowed = matching
+            .iter()
+            .any(|r| r.allow.contains(&op) && (r.paths.is_empty() || r.paths.iter().any(|p| within(path, p))));
+        if !allowed {
+            return Err(format!("Access denied: {} may not {} /{}", subject.describe(), op, path));
+        }
+    }
+    if paths.is_empty() && !matching.iter().any(|r| r.allow.contains(&op)) {
+        return Err(format!("Access denied: {} may not {}", subject.describe(), op));
+    }
+    Ok(())
+}
+
+/// `subject` may read every path below the root
+pub fn reads_everything(rules: &[AclRule], subject: &Subject) -> bool {
+    check(rules, subject, Operation::Get, &[""]).is_ok()
+}
+
+fn matches_client(pattern: &str, subject: &Subject) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.split_once(':') {
+        Some(("cn", name)) => subject.name == Some(name),
+        Some(("fingerprint", fp)) => subject.fingerprint.is_some_and(|f| f.eq_ignore_ascii_case(fp)),
+        Some(("token", name)) => subject.token == Some(name),
+        Some(("net", cidr)) => in_network(subject.ip, cidr),
+        _ => {
+            tracing::warn!("Ignoring unknown ACL client pattern: {}", pattern);
+            false
+        }
+    }
+}
+
+/// `path` is `prefix` itself or lies below it
+fn within(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_matches('/');
+    prefix.is_empty()
+        || path == prefix
+        || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// `ip` is inside `cidr` (`10.0.0.0/8`, `fd00::/8`, or a single address)
+fn in_network(ip: IpAddr, cidr: &str) -> bool {
+    let (net, bits) = match cidr.split_once('/') {
+        Some((net, bits)) => (net, bits.parse::<u32>().ok()),
+        None => (cidr, None),
+    };
+    let Ok(net) = net.parse::<IpAddr>() else {
+        tracing::warn!("Ignoring invalid ACL network: {}", cidr);
+        return false;
+    };
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 => v4,
+    };
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let bits = bits.unwrap_or(32).min(32);
+            let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let bits = bits.unwrap_or(128).min(128);
+            let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_path_prefixes() {
+        assert!(within("artifacts", "artifacts"));
+        assert!(within("artifacts/x/y", "/artifacts/"));
+        assert!(within("anything", ""));
+        assert!(within("anything", "/"));
+        assert!(!within("artifacts2/x", "artifacts"));
+        assert!(!within("art", "artifacts"));
+        assert!(!within("", "artifacts"));
+    }
+
+    #[test]
+    fn delta_needs_read_access() {
+        let delta = Request::Delta { path: "a".into(), size: 1, hash: "00".into(), block_size: 1, meta: None };
+        assert_eq!(operations(&delta), [(0, Operation::Put), (0, Operation::Get)]);
+        let rename = Request::Rename { from: "a".into(), to: "b".into() };
+        assert_eq!(operations(&rename), [(0, Operation::Delete), (1, Operation::Put)]);
+    }
+
+    #[test]
+    fn matches_networks() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(in_network(ip("10.1.2.3"), "10.0.0.0/8"));
+        assert!(!in_network(ip("11.1.2.3"), "10.0.0.0/8"));
+        assert!(in_network(ip("192.168.178.20"), "192.168.178.20"));
+        assert!(!in_network(ip("192.168.178.21"), "192.168.178.20"));
+        assert!(in_network(ip("1.2.3.4"), "0.0.0.0/0"));
+        assert!(in_network(ip("::ffff:10.0.0.1"), "10.0.0.0/8"));
+        assert!(in_network(ip("fd00::1"), "fd00::/8"));
+        assert!(!in_network(ip("fe80::1"), "fd00::/8"));
+        assert!(!in_network(ip("10.0.0.1"), "fd00::/8"));
+        assert!(!in_network(ip("10.0.0.1"), "not-a-network"));
+    }
+}
@@ -14,6 +14,8 @@ pub struct AuditEntry {
     pub timestamp: DateTime<Local>,
     pub event: AuditEvent,
     pub remote: Option<String>,
+    /// Client identity from its certificate (CN/SAN) or token, if authenticated
+    pub client: Option<String>,
     pub path: Option<String>,
     pub size: Option<u64>,
     pub success: bool,
@@ -27,9 +29,19 @@ pub enum AuditEvent {
     ServerStop,
     Connect,
     Disconnect,
+    Authenticated,
+    AuthFailed,
     FileReceived,
     FileRejected,
+    FileDeleted,
+    FileRenamed,
+    DirCreated,
+    FileRestored,
+    ResumeRequest,
+    SignatureRequest,
     ListRequest,
+    ManifestRequest,
+    VersionsRequest,
     StatusRequest,
     FileRequest,
     Error,
@@ -41,6 +53,7 @@ impl AuditEntry {
             timestamp: Local::now(),
             event,
             remote: None,
+            client: None,
             path: None,
             size: None,
             success: true,
@@ -53,6 +66,11 @@ impl AuditEntry {
         self
     }
 
+    pub fn with_client(mut self, client: impl Into<String>) -> Self {
+        self.client = Some(client.into());
+        self
+    }
+
     pub fn with_path(mut self, path: impl Into<String>) -> Self {
         self.path = Some(path.into());
         self
@@ -129,7 +147,11 @@ impl std::fmt::Display for AuditEntry {
         let remote = self.remote.as_deref().unwrap_or("-");
         let path = self.path.as_deref().unwrap_or("-");
         
-        write!(f, "[{}] {} {:?} from {} path={}", ts, status, self.event, remote, path)?;
+        write!(f, "[{}] {} {:?} from {}", ts, status, self.event, remote)?;
+        if let Some(ref client) = self.client {
+            write!(f, " ({})", client)?;
+        }
+        write!(f, " path={}", path)?;
         
         if let Some(size) = self.size {
             write!(f, " size={}", size)?;
@@ -0,0 +1,131 @@
+//! Pre-shared token authentication
+//!
+//! A lightweight alternative to client certificates: the server stores only
+//! blake3 hashes of the tokens, the client presents the plain token in an
+//! `Auth` request on the first stream of every connection.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Failed attempts allowed per IP within `FAILURE_WINDOW`
+const MAX_FAILURES: usize = 5;
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Environment variable that overrides `client.token`
+pub const TOKEN_ENV: &str = "HANK_SYNC_TOKEN";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenEntry {
+    /// Name used in the audit log and in `token:<name>` ACL rules
+    pub name: String,
+    /// blake3 hash of the token, hex encoded (`hank-sync hash-token`)
+    pub hash: String,
+}
+
+/// Hash a token for the server config
+pub fn hash_token(token: &str) -> String {
+    blake3::hash(token.trim().as_bytes()).to_hex().to_string()
+}
+
+/// Name of the configured token matching `token`
+pub fn verify<'a>(tokens: &'a [TokenEntry], token: &str) -> Option<&'a str> {
+    let presented = blake3::hash(token.trim().as_bytes());
+    tokens.iter().find_map(|entry| match blake3::Hash::from_hex(entry.hash.trim()) {
+        // blake3::Hash compares in constant time
+        Ok(expected) if expected == presented => Some(entry.name.as_str()),
+        Ok(_) => None,
+        Err(_) => {
+            tracing::warn!("Ignoring token '{}' with invalid hash", entry.name);
+            None
+        }
+    })
+}
+
+/// Token the client presents: `HANK_SYNC_TOKEN`, else the configured one
+pub fn client_token(configured: Option<&str>) -> Option<String> {
+    std::env::var(TOKEN_ENV)
+        .ok()
+        .or_else(|| configured.map(str::to_string))
+        .filter(|t| !t.trim().is_empty())
+}
+
+/// Sliding-window limit on failed attempts per IP
+#[derive(Default)]
+pub struct RateLimiter {
+    failures: Mutex<HashMap<IpAddr, Vec<Instant>>>,
+}
+
+impl RateLimiter {
+    /// Too many recent failures from `ip`
+    pub fn is_blocked(&self, ip: IpAddr) -> bool {
+        self.is_blocked_at(ip, Instant::now())
+    }
+
+    fn is_blocked_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, times| {
+            times.retain(|t| now.duration_since(*t) < FAILURE_WINDOW);
+            !times.is_empty()
+        });
+        failures.get(&ip).is_some_and(|times| times.len() >= MAX_FAILURES)
+    }
+
+    pub fn record_failure(&self, ip: IpAddr) {
+        self.record_failure_at(ip, Instant::now());
+    }
+
+    fn record_failure_at(&self, ip: IpAddr, at: Instant) {
+        self.failures.lock().unwrap().entry(ip).or_default().push(at);
+    }
+
+    pub fn clear(&self, ip: IpAddr) {
+        self.failures.lock().unwrap().remove(&ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_tokens() {
+        let tokens = vec![
+            TokenEntry { name: "broken".into(), hash: "not hex".into() },
+            TokenEntry { name: "ci".into(), hash: hash_token("secret") },
+        ];
+        assert_eq!(verify(&tokens, "secret"), Some("ci"));
+        assert_eq!(verify(&tokens, " secret\n"), Some("ci"));
+        assert_eq!(verify(&tokens, "Secret"), None);
+        assert_eq!(verify(&tokens, ""), None);
+        assert_eq!(verify(&[], "secret"), None);
+    }
+
+    #[test]
+    fn blocks_within_the_window() {
+        let limiter = RateLimiter::default();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let start = Instant::now();
+        for _ in 0..MAX_FAILURES - 1 {
+            limiter.record_failure_at(ip, start);
+        }
+        assert!(!limiter.is_blocked_at(ip, start));
+        limiter.record_failure_at(ip, start);
+        assert!(limiter.is_blocked_at(ip, start));
+        assert!(!limiter.is_blocked_at(other, start));
+
+        // Failures age out of the window
+        assert!(limiter.is_blocked_at(ip, start + FAILURE_WINDOW - Duration::from_secs(1)));
+        assert!(!limiter.is_blocked_at(ip, start + FAILURE_WINDOW));
+
+        for _ in 0..MAX_FAILURES {
+            limiter.record_failure(ip);
+        }
+        assert!(limiter.is_blocked(ip));
+        limiter.clear(ip);
+        assert!(!limiter.is_blocked(ip));
+    }
+}
@@ -1,47 +1,135 @@
 //! QUIC client - uploads and fetches files
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use futures::StreamExt;
 use quinn::Endpoint;
 use std::{io::Write, path::Path};
-use chrono;
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
-use crate::protocol::{Request, Response};
+use crate::auth;
+use crate::compress::{self, Counted, Traffic};
+use crate::config;
+use crate::crypt::{self, Cipher};
+use crate::delta;
+use crate::known_servers;
+use crate::progress::{self, FileProgress};
+use crate::protocol::{Codec, Compression, FileMeta, ManifestEntry, Request, Response};
 use crate::tls;
 
-async fn connect(server: &str) -> Result<quinn::Connection> {
-    let client_config = tls::client_config()?;
+pub(crate) async fn connect(server: &str) -> Result<quinn::Connection> {
+    connect_with(server, tls::TrustMode::Pin).await
+}
+
+async fn connect_with(server: &str, trust: tls::TrustMode) -> Result<quinn::Connection> {
+    let config = config::load_or_default(None)?;
+    let client_config = tls::client_config(server, trust, &config.tls)?;
     
-    let mut endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
-    endpoint.set_default_client_config(client_config);
+    // Resolve server address (host:port, host may be a name)
+    let addr = tokio::net::lookup_host(server).await
+        .with_context(|| format!("Cannot resolve {}", server))?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No address for {}", server))?;
     
-    // Parse server address
-    let addr = server.parse()?;
+    let bind = if addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+    let mut endpoint = Endpoint::client(bind.parse()?)?;
+    endpoint.set_default_client_config(client_config);
     
-    // Connect (use "localhost" as server name for self-signed certs)
-    let connection = endpoint.connect(addr, "localhost")?.await?;
+    // Certificates are checked against the configured name or the host we dialed
+    let server_name = match &config.client.server_name {
+        Some(name) => name.clone(),
+        None => host_part(server).to_string(),
+    };
+    let connection = endpoint.connect(addr, &server_name)?.await
+        .with_context(|| format!("Cannot connect to {}", server))?;
     tracing::info!("🔗 Connected to {}", server);
     
+    if let Some(token) = auth::client_token(config.client.token.as_deref()) {
+        authenticate(&connection, token).await?;
+    }
+    
     Ok(connection)
 }
 
-pub async fn put(server: &str, path: &Path, dest: Option<&str>) -> Result<()> {
-    let connection = connect(server).await?;
+/// Present the pre-shared token before any other request
+async fn authenticate(connection: &quinn::Connection, token: String) -> Result<()> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_request(&mut send, &Request::Auth { token }).await?;
+    send.finish()?;
     
+    match recv_response(&mut recv).await? {
+        Response::Ok => Ok(()),
+        Response::Error { message } => anyhow::bail!("Authentication failed: {}", message),
+        _ => anyhow::bail!("Unexpected response"),
+    }
+}
+
+/// How often a single-file upload is retried after losing the connection
+const MAX_RETRIES: u32 = 5;
+
+/// Options for `put`
+#[derive(Debug, Default, Clone)]
+pub struct PutOptions {
+    /// Send only changed blocks of files that already exist on the server
+    pub delta: bool,
+    /// Delete remote files and directories that do not exist locally
+    pub mirror: bool,
+    /// Upper bound on deletions per mirror run
+    pub max_delete: usize,
+    /// Only print what would be sent and deleted
+    pub dry_run: bool,
+    /// Compare hashes instead of size + mtime to find unchanged files
+    pub checksum: bool,
+    /// Files of a directory uploaded concurrently (one stream each)
+    pub jobs: usize,
+    /// Send mtime, permissions and symlinks
+    pub preserve: bool,
+    /// zstd level for files worth compressing
+    pub compress: Option<i32>,
+}
+
+/// Options for `get`
+#[derive(Debug, Default, Clone)]
+pub struct GetOptions {
+    /// Files of a directory downloaded concurrently
+    pub jobs: usize,
+    /// Apply mtime, permissions and symlinks from the server
+    pub preserve: bool,
+    /// zstd level the server may compress with
+    pub compress: Option<i32>,
+}
+
+/// Host of a `host:port` / `[v6]:port` address
+fn host_part(server: &str) -> &str {
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+pub async fn put(server: &str, path: &Path, dest: Option<&str>, opts: &PutOptions) -> Result<()> {
     if path.is_file() {
-        send_file(&connection, path, dest).await?;
+        if opts.mirror {
+            anyhow::bail!("--mirror needs a directory");
+        }
+        if opts.dry_run {
+            println!("⬆️  {}", path.display());
+            return Ok(());
+        }
+        send_file(server, path, dest, opts).await?;
     } else if path.is_dir() {
-        send_dir(&connection, path, dest).await?;
+        let connection = connect(server).await?;
+        if opts.mirror {
+            mirror_deletions(&connection, path, &dir_dest(path, dest)?, opts).await?;
+        }
+        send_dir(&connection, path, dest, opts).await?;
+        connection.close(0u32.into(), b"done");
     } else {
         anyhow::bail!("Path does not exist: {:?}", path);
     }
     
-    connection.close(0u32.into(), b"done");
     Ok(())
 }
 
-async fn send_file(connection: &quinn::Connection, path: &Path, dest: Option<&str>) -> Result<()> {
+async fn send_file(server: &str, path: &Path, dest: Option<&str>, opts: &PutOptions) -> Result<()> {
     let filename = path.file_name()
         .ok_or_else(|| anyhow::anyhow!("Invalid filename"))?
         .to_string_lossy();
@@ -52,9 +140,71 @@ async fn send_file(connection: &quinn::Connection, path: &Path, dest: Option<&st
     };
     
     let metadata = fs::metadata(path).await?;
-    let size = metadata.len();
+    let meta = opts.preserve.then(|| FileMeta::from_metadata(&metadata));
+    let meta = meta.as_ref();
+    tracing::info!("📤 Sending: {} → {} ({} bytes)", path.display(), remote_path, metadata.len());
+    
+    let sealed = seal(path, &remote_path).await?;
+    let path = sealed.as_ref().map_or(path, |s| s.path());
+    let size = fs::metadata(path).await?.len();
+    let hash = hash_file(path).await?;
+    let compression = compress::choose(path, opts.compress);
     
-    // Compute hash
+    // Reconnect and resume from the server's partial copy when the connection drops
+    let progress = progress::start(&remote_path, size);
+    let mut attempt = 0;
+    loop {
+        let result = async {
+            let connection = connect(server).await?;
+            let delta = if opts.delta {
+                upload_delta(&connection, path, &remote_path, size, &hash, meta, &progress).await?
+            } else {
+                None
+            };
+            if delta.is_none() {
+                upload_resumable(&connection, path, &remote_path, &hash, meta, compression, &progress).await?;
+            }
+            connection.close(0u32.into(), b"done");
+            Ok::<_, anyhow::Error>(())
+        }.await;
+        
+        match result {
+            Ok(()) => {
+                progress.finish();
+                return Ok(());
+            }
+            Err(e) if attempt < MAX_RETRIES && is_connection_lost(&e) => {
+                // The next attempt reports what the server holds by then
+                progress.restart();
+                attempt += 1;
+                let delay = std::time::Duration::from_secs(2 * attempt as u64);
+                tracing::warn!("⚠️  Connection lost ({}), retry {}/{} in {:?}", e, attempt, MAX_RETRIES, delay);
+                tokio::time::sleep(delay).await;
+            }
+            other => return other,
+        }
+    }
+}
+
+/// Encrypted copy of `path` to upload to `remote_path` instead, if encryption is configured
+pub(crate) async fn seal(path: &Path, remote_path: &str) -> Result<Option<crypt::SealedFile>> {
+    match crypt::active()? {
+        Some(cipher) => Ok(Some(cipher.seal_file(path, remote_path).await?)),
+        None => Ok(None),
+    }
+}
+
+/// blake3 of a local file as the server would store it at `remote_path`
+/// (encrypted if configured)
+pub(crate) async fn hash_content(path: &Path, remote_path: &str) -> Result<String> {
+    match crypt::active()? {
+        Some(cipher) => cipher.hash_sealed(path, remote_path).await,
+        None => hash_file(path).await,
+    }
+}
+
+/// blake3 of a local file, hex encoded
+pub(crate) async fn hash_file(path: &Path) -> Result<String> {
     let mut file = fs::File::open(path).await?;
     let mut hasher = blake3::Hasher::new();
     let mut buf = vec![0u8; 64 * 1024];
@@ -63,89 +213,436 @@ async fn send_file(connection: &quinn::Connection, path: &Path, dest: Option<&st
         if n == 0 { break; }
         hasher.update(&buf[..n]);
     }
-    let hash = hasher.finalize().to_hex().to_string();
-    drop(file);
-    
-    tracing::info!("📤 Sending: {} → {} ({} bytes)", path.display(), remote_path, size);
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// How an upload ended
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Uploaded {
+    Sent(Traffic),
+    /// The server already had the content, no bytes were sent
+    Identical,
+}
+
+/// Upload one file, continuing from whatever the server already holds
+pub(crate) async fn upload_resumable(
+    connection: &quinn::Connection,
+    path: &Path,
+    remote_path: &str,
+    hash: &str,
+    meta: Option<&FileMeta>,
+    compression: Option<Compression>,
+    progress: &FileProgress,
+) -> Result<Uploaded> {
+    let size = fs::metadata(path).await?.len();
+
+    // Ask for the resume offset
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_request(&mut send, &Request::Resume {
+        path: remote_path.to_string(),
+        size,
+        hash: hash.to_string(),
+    }).await?;
+    let offset = match recv_response(&mut recv).await? {
+        Response::Offset { offset } => offset,
+        Response::Error { message } => anyhow::bail!("Server error: {}", message),
+        other => anyhow::bail!("Unexpected response: {:?}", other),
+    };
+    if offset > 0 {
+        tracing::info!("↻ Resuming at {} of {} bytes", offset, size);
+    }
+    progress.skip(offset);
     
     // Open stream
     let (mut send, mut recv) = connection.open_bi().await?;
     
     // Send request
     let request = Request::Put {
-        path: remote_path,
+        path: remote_path.to_string(),
         size,
-        hash: Some(hash),
+        hash: Some(hash.to_string()),
+        offset,
+        meta: meta.cloned(),
+        compression,
     };
     send_request(&mut send, &request).await?;
     
     // Wait for OK
-    let response = recv_response(&mut recv).await?;
-    if !matches!(response, Response::Ok) {
-        anyhow::bail!("Server rejected: {:?}", response);
+    match recv_response(&mut recv).await? {
+        Response::Ok => {}
+        Response::Identical => {
+            tracing::info!("⏭️  Skipped (identical): {}", remote_path);
+            return Ok(Uploaded::Identical);
+        }
+        response => anyhow::bail!("Server rejected: {:?}", response),
     }
     
     // Send file data
     let mut file = fs::File::open(path).await?;
-    let mut sent = 0u64;
-    let mut buf = vec![0u8; 64 * 1024];
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let traffic = send_body(&mut send, progress.track(file.take(size - offset)), compression).await?;
     
-    while sent < size {
-        let n = file.read(&mut buf).await?;
+    // Wait for completion
+    let response = recv_response(&mut recv).await?;
+    match response {
+        Response::Done { written } => {
+            tracing::info!("✅ Done: {} bytes written{}", written, traffic.savings());
+        }
+        Response::Error { message } => {
+            anyhow::bail!("Server rejected {}: {}", remote_path, message);
+        }
+        _ => {
+            tracing::warn!("Unexpected response: {:?}", response);
+        }
+    }
+    
+    Ok(Uploaded::Sent(traffic))
+}
+
+/// Stream `data` (compressed if requested) and finish the stream
+async fn send_body<R>(send: &mut quinn::SendStream, data: R, compression: Option<Compression>) -> Result<Traffic>
+where
+    R: tokio::io::AsyncRead + Unpin + Send,
+{
+    let mut data = Counted::new(data);
+    let mut body = compress::encoder(&mut data, compression);
+    let mut wire = 0u64;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = body.read(&mut buf).await?;
         if n == 0 { break; }
         send.write_all(&buf[..n]).await?;
-        sent += n as u64;
-        
-        // Progress
-        let pct = (sent as f64 / size as f64 * 100.0) as u8;
-        if sent % (1024 * 1024) == 0 || sent == size {
-            tracing::debug!("Progress: {}%", pct);
+        wire += n as u64;
+    }
+    drop(body);
+    send.finish()?;
+    Ok(Traffic { bytes: data.count, wire })
+}
+
+/// Upload only the blocks that differ from the server's copy.
+/// Returns `None` if the server has no copy to diff against.
+async fn upload_delta(
+    connection: &quinn::Connection,
+    path: &Path,
+    remote_path: &str,
+    size: u64,
+    hash: &str,
+    meta: Option<&FileMeta>,
+    progress: &FileProgress,
+) -> Result<Option<Uploaded>> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_request(&mut send, &Request::Signature { path: remote_path.to_string() }).await?;
+    let (block_size, blocks) = match recv_response(&mut recv).await? {
+        Response::Signature { block_size, blocks, .. } => (block_size, blocks),
+        Response::Error { message } => anyhow::bail!("Server error: {}", message),
+        other => anyhow::bail!("Unexpected response: {:?}", other),
+    };
+    if blocks.is_empty() {
+        tracing::info!("No remote copy of {}, sending full file", remote_path);
+        return Ok(None);
+    }
+    
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_request(&mut send, &Request::Delta {
+        path: remote_path.to_string(),
+        size,
+        hash: hash.to_string(),
+        block_size,
+        meta: meta.cloned(),
+    }).await?;
+    
+    match recv_response(&mut recv).await? {
+        Response::Ok => {}
+        Response::Identical => {
+            tracing::info!("⏭️  Skipped (identical): {}", remote_path);
+            return Ok(Some(Uploaded::Identical));
         }
+        response => anyhow::bail!("Server rejected: {:?}", response),
     }
     
+    // Diff on a blocking thread, stream the ops as they are produced
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<delta::Op>(16);
+    let local = path.to_path_buf();
+    let differ = tokio::task::spawn_blocking(move || {
+        let file = std::io::BufReader::new(std::fs::File::open(&local)?);
+        delta::diff(file, block_size, &blocks, |op| {
+            tx.blocking_send(op)
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "delta receiver gone"))
+        })
+    });
+    
+    let mut literal = 0u64;
+    let mut copied = 0u64;
+    let mut position = 0u64;
+    while let Some(op) = rx.recv().await {
+        match &op {
+            delta::Op::Copy(_) => {
+                copied += 1;
+                // The last block of the file may be shorter
+                let len = (block_size as u64).min(size.saturating_sub(position));
+                progress.skip(len);
+                position += len;
+            }
+            delta::Op::Literal(data) => {
+                literal += data.len() as u64;
+                progress.advance(data.len() as u64);
+                position += data.len() as u64;
+            }
+        }
+        delta::write_op(&mut send, &op).await?;
+    }
+    differ.await??;
+    delta::write_end(&mut send).await?;
     send.finish()?;
     
-    // Wait for completion
-    let response = recv_response(&mut recv).await?;
-    match response {
+    match recv_response(&mut recv).await? {
         Response::Done { written } => {
-            tracing::info!("✅ Done: {} bytes written", written);
+            tracing::info!("✅ Done: {} bytes written ({} bytes sent, {} blocks reused)", written, literal, copied);
         }
-        _ => {
-            tracing::warn!("Unexpected response: {:?}", response);
+        Response::Error { message } => {
+            anyhow::bail!("Server rejected {}: {}", remote_path, message);
+        }
+        other => {
+            tracing::warn!("Unexpected response: {:?}", other);
         }
     }
     
-    Ok(())
+    Ok(Some(Uploaded::Sent(Traffic { bytes: size, wire: literal })))
 }
 
-async fn send_dir(connection: &quinn::Connection, path: &Path, dest: Option<&str>) -> Result<()> {
+/// True if the error chain shows the QUIC connection itself went away
+/// (handshake and protocol failures are not worth retrying)
+fn is_connection_lost(err: &anyhow::Error) -> bool {
+    fn transient(e: &quinn::ConnectionError) -> bool {
+        matches!(
+            e,
+            quinn::ConnectionError::TimedOut
+                | quinn::ConnectionError::Reset
+                | quinn::ConnectionError::ConnectionClosed(_)
+        )
+    }
+    
+    err.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<quinn::ConnectionError>() {
+            return transient(e);
+        }
+        if let Some(quinn::WriteError::ConnectionLost(e)) = cause.downcast_ref::<quinn::WriteError>() {
+            return transient(e);
+        }
+        if let Some(quinn::ReadError::ConnectionLost(e)) = cause.downcast_ref::<quinn::ReadError>() {
+            return transient(e);
+        }
+        matches!(
+            cause.downcast_ref::<quinn::ReadExactError>(),
+            Some(quinn::ReadExactError::ReadError(quinn::ReadError::ConnectionLost(e))) if transient(e)
+        )
+    })
+}
+
+/// Remote directory a local directory is uploaded to
+fn dir_dest(path: &Path, dest: Option<&str>) -> Result<String> {
     let base = path.file_name()
         .ok_or_else(|| anyhow::anyhow!("Invalid directory name"))?
         .to_string_lossy();
     
-    let base_dest = match dest {
+    Ok(match dest {
         Some(d) => format!("{}/{}", d.trim_end_matches('/'), base),
         None => base.to_string(),
+    })
+}
+
+async fn send_dir(connection: &quinn::Connection, path: &Path, dest: Option<&str>, opts: &PutOptions) -> Result<()> {
+    let base_dest = dir_dest(path, dest)?;
+    
+    // One manifest for the whole subtree; without it everything is sent
+    let remote: std::collections::HashMap<String, ManifestEntry> = match manifest(connection, &base_dest, opts.checksum).await {
+        Ok(entries) => entries.into_iter().map(|e| (e.path.clone(), e)).collect(),
+        Err(e) => {
+            tracing::warn!("No remote manifest ({:#}), sending all files", e);
+            Default::default()
+        }
     };
     
+    let mut unchanged = 0;
+    let mut queue = Vec::new();
+    let mut queued_bytes = 0;
     for entry in walkdir::WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
-        if entry.file_type().is_file() {
-            let rel = entry.path().strip_prefix(path)?;
-            let remote = format!("{}/{}", base_dest, rel.to_string_lossy().replace('\\', "/"));
+        let rel = entry.path().strip_prefix(path)?.to_string_lossy().replace('\\', "/");
+        let remote_path = format!("{}/{}", base_dest, rel);
+        
+        if entry.file_type().is_symlink() {
+            if !opts.preserve {
+                tracing::warn!("Skipping symlink {} (use --preserve)", entry.path().display());
+                continue;
+            }
+            let target = std::fs::read_link(entry.path())?.to_string_lossy().replace('\\', "/");
+            if opts.dry_run {
+                println!("🔗 {} -> {}", remote_path, target);
+                continue;
+            }
+            let meta = FileMeta { symlink: Some(target), ..Default::default() };
+            queue.push((entry.into_path(), remote_path, None, Some(meta)));
+        } else if entry.file_type().is_file() {
+            let metadata = entry.metadata()?;
+            let mut hash = None;
+            if let Some(existing) = remote.get(&rel).filter(|e| e.size == metadata.len()) {
+                let same = if opts.checksum {
+                    let local = hash_content(entry.path(), &remote_path).await?;
+                    let same = existing.hash.as_deref() == Some(local.as_str());
+                    hash = Some(local);
+                    same
+                } else {
+                    // Quick check: only a copy carrying our mtime (`--preserve`) is taken as
+                    // unchanged. A newer one may still be older content (`cp -p`, restored
+                    // backups); those go up and the server's hash check skips them if identical.
+                    let modified = metadata.modified()?.duration_since(std::time::UNIX_EPOCH)?.as_secs();
+                    existing.modified == modified
+                };
+                if same {
+                    tracing::debug!("Unchanged: {}", remote_path);
+                    unchanged += 1;
+                    continue;
+                }
+            }
             
-            send_file_with_path(connection, entry.path(), &remote).await?;
+            if opts.dry_run {
+                println!("⬆️  {}", remote_path);
+                continue;
+            }
+            let meta = opts.preserve.then(|| FileMeta::from_metadata(&metadata));
+            queued_bytes += metadata.len();
+            queue.push((entry.into_path(), remote_path, hash, meta));
         }
     }
+    if opts.dry_run {
+        return Ok(());
+    }
+    let files = queue.iter().filter(|(_, _, _, meta)| meta.as_ref().is_none_or(|m| m.symlink.is_none())).count();
+    progress::expect(files as u64, queued_bytes);
     
+    // Each file on its own stream; `buffered` keeps results in walk order
+    let mut results = futures::stream::iter(queue)
+        .map(|(local, remote_path, hash, meta)| async move {
+            let result = async {
+                match meta {
+                    Some(meta) if meta.symlink.is_some() => {
+                        send_symlink(connection, &remote_path, meta).await.map(|()| Uploaded::Sent(Traffic::default()))
+                    }
+                    meta => {
+                        let size = fs::metadata(&local).await?.len();
+                        tracing::info!("📤 Sending: {} → {} ({} bytes)", local.display(), remote_path, size);
+                        let sealed = seal(&local, &remote_path).await?;
+                        let local = sealed.as_ref().map_or(local.as_path(), |s| s.path());
+                        let hash = match hash {
+                            Some(h) => h,
+                            None => hash_file(local).await?,
+                        };
+                        let compression = compress::choose(local, opts.compress);
+                        send_file_with_path(connection, local, &remote_path, &hash, meta.as_ref(), compression).await
+                    }
+                }
+            }.await;
+            (remote_path, result)
+        })
+        .buffered(opts.jobs.max(1));
+    
+    let mut sent = 0;
+    let mut traffic = Traffic::default();
+    let mut identical = 0;
+    let mut errors = Vec::new();
+    while let Some((remote_path, result)) = results.next().await {
+        match result {
+            Ok(Uploaded::Sent(t)) => {
+                sent += 1;
+                traffic += t;
+            }
+            Ok(Uploaded::Identical) => identical += 1,
+            Err(e) => {
+                tracing::error!("❌ {}: {:#}", remote_path, e);
+                errors.push(format!("{}: {:#}", remote_path, e));
+            }
+        }
+    }
+    
+    tracing::info!(
+        "✅ {} file(s) sent ({} bytes{}), {} skipped (identical), {} unchanged, {} failed",
+        sent, traffic.bytes, traffic.savings(), identical, unchanged, errors.len()
+    );
+    if !errors.is_empty() {
+        anyhow::bail!("{} file(s) failed:\n  {}", errors.len(), errors.join("\n  "));
+    }
     Ok(())
 }
 
-async fn send_file_with_path(connection: &quinn::Connection, path: &Path, remote_path: &str) -> Result<()> {
-    let metadata = fs::metadata(path).await?;
-    let size = metadata.len();
+/// Delete everything below `remote_dir` that has no local counterpart in `path`
+async fn mirror_deletions(connection: &quinn::Connection, path: &Path, remote_dir: &str, opts: &PutOptions) -> Result<()> {
+    let mut local_files = std::collections::HashSet::new();
+    let mut local_dirs = std::collections::HashSet::new();
+    for entry in walkdir::WalkDir::new(path).min_depth(1).into_iter().filter_map(|e| e.ok()) {
+        let rel = entry.path().strip_prefix(path)?.to_string_lossy().replace('\\', "/");
+        if entry.file_type().is_dir() {
+            local_dirs.insert(rel);
+        } else {
+            local_files.insert(rel);
+        }
+    }
+    
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_request(&mut send, &Request::List { path: remote_dir.to_string(), recursive: true, long: false }).await?;
+    let remote = match recv_response(&mut recv).await? {
+        Response::List { entries } => entries,
+        Response::Error { message } => anyhow::bail!("Server error: {}", message),
+        other => anyhow::bail!("Unexpected response: {:?}", other),
+    };
     
-    tracing::info!("📤 Sending: {} → {} ({} bytes)", path.display(), remote_path, size);
+    // Files first, then directories deepest first so each one is empty by then
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for entry in remote {
+        if entry.is_dir && !local_dirs.contains(&entry.name) {
+            dirs.push(entry.name);
+        } else if !entry.is_dir && !local_files.contains(&entry.name) {
+            files.push(entry.name);
+        }
+    }
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.matches('/').count()));
+    let doomed: Vec<String> = files.into_iter().chain(dirs).collect();
+    
+    // A dry run shows everything, that is where a too large deletion gets checked
+    if doomed.len() > opts.max_delete && !opts.dry_run {
+        anyhow::bail!(
+            "Mirror would delete {} remote entries (limit {}), check with --dry-run and raise --max-delete",
+            doomed.len(),
+            opts.max_delete
+        );
+    }
+    
+    for name in &doomed {
+        let remote_path = format!("{}/{}", remote_dir.trim_end_matches('/'), name);
+        println!("🗑️  {}", remote_path);
+        if !opts.dry_run {
+            delete(connection, &remote_path, false).await?;
+        }
+    }
+    if opts.dry_run && doomed.len() > opts.max_delete {
+        println!("({} deletions, more than --max-delete {}: a real run would stop here)", doomed.len(), opts.max_delete);
+    } else if !doomed.is_empty() && !opts.dry_run {
+        tracing::info!("🗑️  Deleted {} remote entries", doomed.len());
+    }
+    
+    Ok(())
+}
+
+async fn send_file_with_path(
+    connection: &quinn::Connection,
+    path: &Path,
+    remote_path: &str,
+    hash: &str,
+    meta: Option<&FileMeta>,
+    compression: Option<Compression>,
+) -> Result<Uploaded> {
+    let size = fs::metadata(path).await?.len();
+    let progress = progress::start(remote_path, size);
     
     // Open stream
     let (mut send, mut recv) = connection.open_bi().await?;
@@ -154,32 +651,57 @@ async fn send_file_with_path(connection: &quinn::Connection, path: &Path, remote
     let request = Request::Put {
         path: remote_path.to_string(),
         size,
-        hash: None, // Skip hash for directories (faster)
+        hash: Some(hash.to_string()),
+        offset: 0,
+        meta: meta.cloned(),
+        compression,
     };
     send_request(&mut send, &request).await?;
     
     // Wait for OK
-    let response = recv_response(&mut recv).await?;
-    if !matches!(response, Response::Ok) {
-        anyhow::bail!("Server rejected: {:?}", response);
+    match recv_response(&mut recv).await? {
+        Response::Ok => {}
+        Response::Identical => {
+            tracing::info!("⏭️  Skipped (identical): {}", remote_path);
+            progress.finish();
+            return Ok(Uploaded::Identical);
+        }
+        response => anyhow::bail!("Server rejected: {:?}", response),
     }
     
     // Send file data
-    let mut file = fs::File::open(path).await?;
-    let mut buf = vec![0u8; 64 * 1024];
+    let file = fs::File::open(path).await?;
+    let traffic = send_body(&mut send, progress.track(file.take(size)), compression).await?;
     
-    loop {
-        let n = file.read(&mut buf).await?;
-        if n == 0 { break; }
-        send.write_all(&buf[..n]).await?;
+    // Wait for completion
+    if let Response::Error { message } = recv_response(&mut recv).await? {
+        anyhow::bail!("Server rejected {}: {}", remote_path, message);
     }
+    progress.finish();
     
-    send.finish()?;
+    Ok(Uploaded::Sent(traffic))
+}
+
+/// Recreate a symlink on the server (`meta.symlink` holds the target)
+async fn send_symlink(connection: &quinn::Connection, remote_path: &str, meta: FileMeta) -> Result<()> {
+    tracing::info!("🔗 Linking: {} -> {}", remote_path, meta.symlink.as_deref().unwrap_or_default());
     
-    // Wait for completion
-    let _ = recv_response(&mut recv).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_request(&mut send, &Request::Put {
+        path: remote_path.to_string(),
+        size: 0,
+        hash: None,
+        offset: 0,
+        meta: Some(meta),
+        compression: None,
+    }).await?;
+    send.finish()?;
     
-    Ok(())
+    match recv_response(&mut recv).await? {
+        Response::Done { .. } => Ok(()),
+        Response::Error { message } => anyhow::bail!("Server rejected {}: {}", remote_path, message),
+        other => anyhow::bail!("Unexpected response: {:?}", other),
+    }
 }
 
 pub async fn list(server: &str, path: &str) -> Result<()> {
@@ -217,8 +739,8 @@ async fn list_with(server: &str, path: &str, recursive: bool, long: bool) -> Res
                 };
                 if long {
                     let ts = entry.modified.map(|m| {
-                        let dt = chrono::NaiveDateTime::from_timestamp_opt(m as i64, 0)
-                            .unwrap_or_else(|| chrono::NaiveDateTime::from_timestamp_opt(0, 0).unwrap());
+                        let dt = chrono::DateTime::from_timestamp(m as i64, 0)
+                            .unwrap_or_default();
                         dt.format("%Y-%m-%d %H:%M").to_string()
                     }).unwrap_or_else(|| "-".to_string());
                     println!("{}{} {:>10} {} {}", indent, type_indicator, entry.size, ts, name);
@@ -251,11 +773,12 @@ pub async fn status(server: &str) -> Result<()> {
     let response = recv_response(&mut recv).await?;
     
     match response {
-        Response::Status { root, total_size, file_count } => {
+        Response::Status { root, total_size, file_count, physical_size } => {
             println!("📊 Server Status:");
             println!("  Root: {}", root);
             println!("  Files: {}", file_count);
             println!("  Total size: {} MB", total_size / 1024 / 1024);
+            println!("  Stored size: {} MB (incl. versions, shared content counted once)", physical_size / 1024 / 1024);
         }
         _ => {
             tracing::error!("Unexpected response: {:?}", response);
@@ -266,93 +789,544 @@ pub async fn status(server: &str) -> Result<()> {
     Ok(())
 }
 
-pub async fn view(server: &str, path: &str) -> Result<()> {
+/// Byte range selection for `view`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ViewRange {
+    pub offset: u64,
+    pub length: Option<u64>,
+    /// Show only the last N bytes (overrides offset)
+    pub tail: Option<u64>,
+}
+
+pub async fn view(server: &str, path: &str, range: ViewRange, compress: Option<i32>) -> Result<()> {
     let connection = connect(server).await?;
+    if let Some(cipher) = crypt::active()? {
+        view_sealed(&connection, cipher, path, range, compress).await?;
+        connection.close(0u32.into(), b"done");
+        return Ok(());
+    }
+
+    let (offset, length) = match range.tail {
+        Some(n) => {
+            // Zero-length request to learn the file size
+            let total = request_file(&connection, path, 0, Some(0), false, None).await?.total;
+            (total.saturating_sub(n), None)
+        }
+        None => (range.offset, range.length),
+    };
 
+    let FileReply { mut recv, size, compression, .. } =
+        request_file(&connection, path, offset, length, false, compress).await?;
+    let mut body = compress::decoder(&mut recv, compression);
+    let mut remaining = size as usize;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut out = std::io::stdout();
+    while remaining > 0 {
+        let to_read = std::cmp::min(remaining, buf.len());
+        let n = body.read(&mut buf[..to_read]).await?;
+        if n == 0 { break; }
+        out.write_all(&buf[..n])?;
+        remaining -= n;
+    }
+
+    connection.close(0u32.into(), b"done");
+    Ok(())
+}
+
+/// `view` of an encrypted file: fetch the chunks covering the range and decrypt them
+async fn view_sealed(
+    connection: &quinn::Connection,
+    cipher: &Cipher,
+    path: &str,
+    range: ViewRange,
+    compress: Option<i32>,
+) -> Result<()> {
+    let FileReply { mut recv, size, total, .. } =
+        request_file(connection, path, 0, Some(crypt::HEADER_LEN as u64), false, None).await?;
+    let mut header = vec![0u8; size as usize];
+    recv.read_exact(&mut header).await?;
+    let file = cipher.check_header(&header).with_context(|| format!("Cannot decrypt {}", path))?;
+    let plain_total = Cipher::plain_len(total)
+        .ok_or_else(|| anyhow::anyhow!("Cannot decrypt {}: unexpected size {}", path, total))?;
+
+    let (start, end) = match range.tail {
+        Some(n) => (plain_total.saturating_sub(n), plain_total),
+        None => {
+            let start = range.offset.min(plain_total);
+            (start, range.length.map_or(plain_total, |l| start.saturating_add(l).min(plain_total)))
+        }
+    };
+    if start >= end {
+        return Ok(());
+    }
+
+    let (chunk, sealed_chunk) = (crypt::CHUNK as u64, crypt::SEALED_CHUNK as u64);
+    let (first, last) = (start / chunk, (end - 1) / chunk);
+    let offset = crypt::HEADER_LEN as u64 + first * sealed_chunk;
+    let length = (crypt::HEADER_LEN as u64 + (last + 1) * sealed_chunk).min(total) - offset;
+    let FileReply { mut recv, compression, .. } =
+        request_file(connection, path, offset, Some(length), false, compress).await?;
+    let mut body = compress::decoder(&mut recv, compression);
+
+    let chunks = Cipher::chunks(total);
+    let mut remaining = length;
+    let mut sealed = vec![0u8; crypt::SEALED_CHUNK];
+    let mut out = std::io::stdout();
+    for index in first..=last {
+        let n = remaining.min(sealed_chunk) as usize;
+        body.read_exact(&mut sealed[..n]).await?;
+        remaining -= n as u64;
+        let plain = cipher.open_chunk(&file, index, index + 1 == chunks, &sealed[..n])
+            .with_context(|| format!("Cannot decrypt {}", path))?;
+        let chunk_start = index * chunk;
+        let from = start.saturating_sub(chunk_start) as usize;
+        let to = ((end - chunk_start) as usize).min(plain.len());
+        out.write_all(&plain[from..to])?;
+    }
+    Ok(())
+}
+
+/// Delete a file (or a directory tree with `recursive`) on the server
+pub async fn remove(server: &str, path: &str, recursive: bool) -> Result<()> {
+    let connection = connect(server).await?;
+    delete(&connection, path, recursive).await?;
+    println!("🗑️  Deleted {}", path);
+    connection.close(0u32.into(), b"done");
+    Ok(())
+}
+
+/// Move or rename a file or directory on the server
+pub async fn rename(server: &str, from: &str, to: &str) -> Result<()> {
+    let connection = connect(server).await?;
+    expect_ok(&connection, &Request::Rename { from: from.to_string(), to: to.to_string() }).await?;
+    println!("📦 Moved {} -> {}", from, to);
+    connection.close(0u32.into(), b"done");
+    Ok(())
+}
+
+/// Create a directory (and missing parents) on the server
+pub async fn mkdir(server: &str, path: &str) -> Result<()> {
+    let connection = connect(server).await?;
+    expect_ok(&connection, &Request::Mkdir { path: path.to_string() }).await?;
+    println!("📁 Created {}", path);
+    connection.close(0u32.into(), b"done");
+    Ok(())
+}
+
+/// Show the versions the server keeps of a file
+pub async fn versions(server: &str, path: &str) -> Result<()> {
+    let connection = connect(server).await?;
     let (mut send, mut recv) = connection.open_bi().await?;
-    send_request(&mut send, &Request::Get { path: path.to_string() }).await?;
+    send_request(&mut send, &Request::Versions { path: path.to_string() }).await?;
 
-    let response = recv_response(&mut recv).await?;
-    match response {
-        Response::File { size } => {
-            let mut remaining = size as usize;
-            let mut buf = vec![0u8; 64 * 1024];
-            let mut out = std::io::stdout();
-            while remaining > 0 {
-                let to_read = std::cmp::min(remaining, buf.len());
-                let n = match recv.read(&mut buf[..to_read]).await? {
-                    Some(n) => n,
-                    None => break,
-                };
-                if n == 0 { break; }
-                out.write_all(&buf[..n])?;
-                remaining -= n;
+    match recv_response(&mut recv).await? {
+        Response::Versions { versions } if versions.is_empty() => println!("No versions of {}", path),
+        Response::Versions { versions } => {
+            println!("🕘 Versions of {}:", path);
+            for version in versions {
+                let modified = chrono::DateTime::from_timestamp(version.modified as i64, 0)
+                    .unwrap_or_default()
+                    .format("%Y-%m-%d %H:%M");
+                println!("  {} {:>10} {}", version.id, version.size, modified);
             }
         }
-        Response::Error { message } => {
-            anyhow::bail!("Server error: {}", message);
-        }
-        other => {
-            anyhow::bail!("Unexpected response: {:?}", other);
-        }
+        Response::Error { message } => anyhow::bail!("Server error: {}", message),
+        other => anyhow::bail!("Unexpected response: {:?}", other),
     }
 
     connection.close(0u32.into(), b"done");
     Ok(())
 }
 
-pub async fn get(server: &str, path: &str, dest: Option<&Path>) -> Result<()> {
+/// Replace a file on the server with one of its versions
+pub async fn restore(server: &str, path: &str, version: &str) -> Result<()> {
     let connection = connect(server).await?;
+    expect_ok(&connection, &Request::Restore { path: path.to_string(), version: version.to_string() }).await?;
+    println!("🕘 Restored {} from version {}", path, version);
+    connection.close(0u32.into(), b"done");
+    Ok(())
+}
 
+pub async fn get(server: &str, path: &str, dest: Option<&Path>, opts: &GetOptions) -> Result<()> {
+    let connection = connect(server).await?;
+    let name = Path::new(path).file_name();
+    
+    if is_remote_dir(&connection, path).await? {
+        let dest_dir = match (dest, name) {
+            (Some(d), Some(name)) if d.is_dir() => d.join(name),
+            (Some(d), _) => d.to_path_buf(),
+            (None, Some(name)) => std::path::PathBuf::from(name),
+            (None, None) => std::path::PathBuf::from("."),
+        };
+        get_dir(&connection, path, &dest_dir, opts).await?;
+    } else {
+        let filename = name.ok_or_else(|| anyhow::anyhow!("Invalid filename"))?;
+        let dest_path = match dest {
+            Some(d) if d.is_dir() => d.join(filename),
+            Some(d) => d.to_path_buf(),
+            None => std::path::PathBuf::from(filename),
+        };
+        let root = dest_path.parent().unwrap_or(Path::new(""));
+        download(&connection, path, &dest_path, opts.preserve.then_some(root), opts.compress).await?;
+    }
+    
+    connection.close(0u32.into(), b"done");
+    Ok(())
+}
+
+/// Zero-length `Get` to find out whether `path` is a directory
+async fn is_remote_dir(connection: &quinn::Connection, path: &str) -> Result<bool> {
     let (mut send, mut recv) = connection.open_bi().await?;
-    send_request(&mut send, &Request::Get { path: path.to_string() }).await?;
+    send_request(&mut send, &Request::Get {
+        path: path.to_string(),
+        offset: 0,
+        length: Some(0),
+        meta: false,
+        compression: None,
+    }).await?;
+    
+    match recv_response(&mut recv).await? {
+        Response::Directory => Ok(true),
+        Response::File { .. } => Ok(false),
+        Response::Error { message } => anyhow::bail!("Server error: {}", message),
+        other => anyhow::bail!("Unexpected response: {:?}", other),
+    }
+}
 
-    let response = recv_response(&mut recv).await?;
-    match response {
-        Response::File { size } => {
-            let filename = std::path::Path::new(path)
-                .file_name()
-                .ok_or_else(|| anyhow::anyhow!("Invalid filename"))?;
-            let mut dest_path = match dest {
-                Some(d) if d.is_dir() => d.join(filename),
-                Some(d) => d.to_path_buf(),
-                None => std::path::PathBuf::from(filename),
-            };
-            if let Some(parent) = dest_path.parent() {
-                if !parent.as_os_str().is_empty() {
-                    fs::create_dir_all(parent).await?;
+/// Recreate a remote directory tree below `dest_dir`, `jobs` files at a time
+async fn get_dir(connection: &quinn::Connection, path: &str, dest_dir: &Path, opts: &GetOptions) -> Result<()> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_request(&mut send, &Request::List { path: path.to_string(), recursive: true, long: true }).await?;
+    let entries = match recv_response(&mut recv).await? {
+        Response::List { entries } => entries,
+        Response::Error { message } => anyhow::bail!("Server error: {}", message),
+        other => anyhow::bail!("Unexpected response: {:?}", other),
+    };
+    
+    fs::create_dir_all(dest_dir).await?;
+    let mut queue = Vec::new();
+    let mut queued_bytes = 0;
+    let mut unchanged = 0;
+    for entry in entries {
+        // Never let a listing entry point outside the destination
+        let rel = Path::new(&entry.name);
+        if rel.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+            tracing::warn!("Skipping suspicious entry: {}", entry.name);
+            continue;
+        }
+        let local = dest_dir.join(rel);
+        if entry.is_dir {
+            fs::create_dir_all(&local).await?;
+            continue;
+        }
+        let modified = entry.modified.map(|m| std::time::UNIX_EPOCH + std::time::Duration::from_secs(m));
+        
+        // A complete copy from an earlier run is left alone
+        if let Ok(meta) = fs::metadata(&local).await
+            && meta.len() == entry.size
+            && modified.is_some()
+            && meta.modified().ok() == modified
+        {
+            unchanged += 1;
+            continue;
+        }
+        let remote_path = format!("{}/{}", path.trim_end_matches('/'), entry.name);
+        queued_bytes += entry.size;
+        queue.push((remote_path, local, modified));
+    }
+    progress::expect(queue.len() as u64, queued_bytes);
+    
+    let mut results = futures::stream::iter(queue)
+        .map(|(remote_path, local, modified)| async move {
+            let result = async {
+                let traffic = download(connection, &remote_path, &local, opts.preserve.then_some(dest_dir), opts.compress).await?;
+                // With --preserve the server's attributes were applied already
+                if !opts.preserve
+                    && let Some(modified) = modified
+                {
+                    std::fs::File::options().write(true).open(&local)?.set_modified(modified)?;
                 }
+                Ok::<_, anyhow::Error>(traffic)
+            }.await;
+            (remote_path, result)
+        })
+        .buffered(opts.jobs.max(1));
+    
+    let mut fetched = 0;
+    let mut traffic = Traffic::default();
+    let mut errors = Vec::new();
+    while let Some((remote_path, result)) = results.next().await {
+        match result {
+            Ok(t) => {
+                fetched += 1;
+                traffic += t;
             }
-            let mut file = fs::File::create(&dest_path).await?;
-            let mut remaining = size as usize;
-            let mut buf = vec![0u8; 64 * 1024];
-            while remaining > 0 {
-                let to_read = std::cmp::min(remaining, buf.len());
-                let n = match recv.read(&mut buf[..to_read]).await? {
-                    Some(n) => n,
-                    None => break,
-                };
-                if n == 0 { break; }
-                file.write_all(&buf[..n]).await?;
-                remaining -= n;
+            Err(e) => {
+                tracing::error!("❌ {}: {:#}", remote_path, e);
+                errors.push(format!("{}: {:#}", remote_path, e));
             }
-            file.flush().await?;
-            tracing::info!("✅ Saved: {}", dest_path.display());
         }
-        Response::Error { message } => {
-            anyhow::bail!("Server error: {}", message);
+    }
+    
+    tracing::info!(
+        "✅ {} file(s) fetched ({} bytes{}), {} unchanged, {} failed",
+        fetched, traffic.bytes, traffic.savings(), unchanged, errors.len()
+    );
+    if !errors.is_empty() {
+        anyhow::bail!("{} file(s) failed:\n  {}", errors.len(), errors.join("\n  "));
+    }
+    Ok(())
+}
+
+/// Download `path` into `dest_path`, resuming a previous partial download.
+/// With `preserve_within`, attributes are applied and symlinks recreated
+/// as long as their target stays below that directory. `compress` lets the
+/// server compress at up to that zstd level.
+pub(crate) async fn download(
+    connection: &quinn::Connection,
+    path: &str,
+    dest_path: &Path,
+    preserve_within: Option<&Path>,
+    compress: Option<i32>,
+) -> Result<Traffic> {
+    if let Some(parent) = dest_path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent).await?;
+    }
+
+    // Continue a previous interrupted download if its partial file is still there
+    let previous = find_partial(dest_path).await;
+    let mut offset = match &previous {
+        Some(p) => fs::metadata(p).await.map(|m| m.len()).unwrap_or(0),
+        None => 0,
+    };
+    let mut reply = request_file(connection, path, offset, None, preserve_within.is_some(), compress).await;
+
+    // The partial's name records the version of the file it came from
+    let stale = match (&previous, &reply) {
+        (Some(p), Ok(r)) => *p != partial_path(dest_path, r.total, r.modified),
+        // Most likely the file shrank below what we hold
+        (Some(_), Err(_)) => offset > 0,
+        (None, _) => false,
+    };
+    if let Some(previous) = previous.filter(|_| stale) {
+        tracing::info!("↻ {} changed on the server, starting over", path);
+        fs::remove_file(&previous).await?;
+        if offset > 0 {
+            offset = 0;
+            reply = request_file(connection, path, 0, None, preserve_within.is_some(), compress).await;
         }
-        other => {
-            anyhow::bail!("Unexpected response: {:?}", other);
+    }
+    let FileReply { mut recv, size, total, modified, meta, compression } = reply?;
+    let partial = partial_path(dest_path, total, modified);
+    if let (Some(root), Some(target)) = (preserve_within, meta.as_ref().and_then(|m| m.symlink.as_deref())) {
+        let _ = fs::remove_file(&partial).await;
+        create_symlink(root, dest_path, target)?;
+        return Ok(Traffic::default());
+    }
+    if offset > 0 {
+        tracing::info!("↻ Resuming {} at {} bytes", dest_path.display(), offset);
+    }
+    let progress = progress::start(path, offset + size);
+    progress.skip(offset);
+
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(&partial).await?;
+    let mut wire = Counted::new(&mut recv);
+    let mut body = compress::decoder(&mut wire, compression);
+    let mut remaining = size as usize;
+    let mut buf = vec![0u8; 64 * 1024];
+    while remaining > 0 {
+        let to_read = std::cmp::min(remaining, buf.len());
+        let n = body.read(&mut buf[..to_read]).await?;
+        if n == 0 { break; }
+        file.write_all(&buf[..n]).await?;
+        remaining -= n;
+        progress.advance(n as u64);
+    }
+    file.flush().await?;
+    drop(file);
+    drop(body);
+    let traffic = Traffic { bytes: size - remaining as u64, wire: wire.count };
+
+    if remaining > 0 {
+        anyhow::bail!("Download incomplete ({} bytes missing), run get again to resume", remaining);
+    }
+    // The complete ciphertext stays as partial if decryption fails
+    let finished = match crypt::active()? {
+        Some(cipher) => {
+            let plain = partial.with_extension("hank-plain");
+            // Left over from an earlier attempt; a symlink there is removed, not followed
+            let _ = fs::remove_file(&plain).await;
+            cipher.open_file(&partial, &plain).await
+                .with_context(|| format!("Cannot decrypt {}", path))?;
+            fs::remove_file(&partial).await?;
+            plain
         }
+        None => partial,
+    };
+    if let Some(meta) = &meta {
+        meta.apply(&finished)?;
     }
+    fs::rename(&finished, dest_path).await?;
+    progress.finish();
+    tracing::info!("✅ Saved: {}{}", dest_path.display(), traffic.savings());
+    Ok(traffic)
+}
 
+/// Recreate a symlink from the server, refusing targets outside `root`
+fn create_symlink(root: &Path, link: &Path, target: &str) -> Result<()> {
+    let rel = link.strip_prefix(root).unwrap_or(link).to_string_lossy().replace('\\', "/");
+    crate::sandbox::check_link_target(&rel, target)
+        .with_context(|| format!("Refusing to create symlink {}", link.display()))?;
+    
+    #[cfg(unix)]
+    {
+        if std::fs::symlink_metadata(link).is_ok_and(|m| !m.is_dir()) {
+            std::fs::remove_file(link)?;
+        }
+        std::os::unix::fs::symlink(target, link)?;
+        tracing::info!("🔗 Linked: {} -> {}", link.display(), target);
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    anyhow::bail!("Cannot create symlink {} -> {} on this platform", link.display(), target)
+}
+
+/// Files below `path` on the server (with blake3 if `hash`)
+pub(crate) async fn manifest(connection: &quinn::Connection, path: &str, hash: bool) -> Result<Vec<ManifestEntry>> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_request(&mut send, &Request::Manifest { path: path.to_string(), hash }).await?;
+
+    match recv_response(&mut recv).await? {
+        Response::Manifest { entries } => Ok(entries),
+        Response::Error { message } => anyhow::bail!("Server error: {}", message),
+        other => anyhow::bail!("Unexpected response: {:?}", other),
+    }
+}
+
+/// Remove a file or directory on the server
+pub(crate) async fn delete(connection: &quinn::Connection, path: &str, recursive: bool) -> Result<()> {
+    let request = Request::Delete { path: path.to_string(), recursive };
+    expect_ok(connection, &request).await
+}
+
+/// Send a request that is answered with a plain `Ok`
+async fn expect_ok(connection: &quinn::Connection, request: &Request) -> Result<()> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send_request(&mut send, request).await?;
+
+    match recv_response(&mut recv).await? {
+        Response::Ok => Ok(()),
+        Response::Error { message } => anyhow::bail!("Server error: {}", message),
+        other => anyhow::bail!("Unexpected response: {:?}", other),
+    }
+}
+
+/// Header of a `File` response; `size` bytes follow on `recv`
+struct FileReply {
+    recv: quinn::RecvStream,
+    size: u64,
+    /// Full file size
+    total: u64,
+    /// Seconds since the epoch
+    modified: u64,
+    meta: Option<FileMeta>,
+    /// How the bytes on `recv` are compressed
+    compression: Option<Compression>,
+}
+
+/// Request `length` bytes of `path` from `offset` (with attributes if `meta`),
+/// allowing the server to compress up to zstd level `compress`
+async fn request_file(
+    connection: &quinn::Connection,
+    path: &str,
+    offset: u64,
+    length: Option<u64>,
+    meta: bool,
+    compress: Option<i32>,
+) -> Result<FileReply> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    let compression = compress.map(|level| Compression { codec: Codec::Zstd, level });
+    send_request(&mut send, &Request::Get { path: path.to_string(), offset, length, meta, compression }).await?;
+
+    match recv_response(&mut recv).await? {
+        Response::File { size, total, modified, meta, compression, .. } => {
+            Ok(FileReply { recv, size, total, modified, meta, compression })
+        }
+        Response::Directory => anyhow::bail!("{} is a directory", path),
+        Response::Error { message } => anyhow::bail!("Server error: {}", message),
+        other => anyhow::bail!("Unexpected response: {:?}", other),
+    }
+}
+
+/// Hidden sibling used while downloading a file of `total` bytes last
+/// modified at `modified`: `.<name>.<total>-<modified>.hank-part`
+fn partial_path(dest: &Path, total: u64, modified: u64) -> std::path::PathBuf {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!(".{}.{}-{}.hank-part", name, total, modified))
+}
+
+/// Partial download of `dest` left behind by an earlier run, if any
+async fn find_partial(dest: &Path) -> Option<std::path::PathBuf> {
+    let name = dest.file_name()?.to_string_lossy();
+    let prefix = format!(".{}.", name);
+    let dir = dest.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut entries = fs::read_dir(dir).await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let version = file_name.strip_prefix(&prefix).and_then(|rest| rest.strip_suffix(".hank-part"));
+        // `a.txt` must not pick up the partial of `a.txt.bak`
+        let ours = version.and_then(|v| v.split_once('-'))
+            .is_some_and(|(t, m)| t.parse::<u64>().is_ok() && m.parse::<u64>().is_ok());
+        if ours {
+            return Some(entry.path());
+        }
+    }
+    None
+}
+
+/// Show pinned fingerprints (all, or only `server`)
+pub fn trust_show(server: Option<&str>) -> Result<()> {
+    let known = known_servers::load()?;
+    let mut shown = 0;
+    for (addr, pin) in &known.servers {
+        if server.is_some_and(|s| s != addr) {
+            continue;
+        }
+        println!("🔐 {} {} (pinned {})", addr, pin.fingerprint, pin.pinned_at.format("%Y-%m-%d %H:%M"));
+        shown += 1;
+    }
+    if shown == 0 {
+        println!("No pinned servers");
+    }
+    Ok(())
+}
+
+/// Connect once and pin whatever certificate the server presents
+pub async fn trust_accept(server: &str) -> Result<()> {
+    let connection = connect_with(server, tls::TrustMode::Accept).await?;
     connection.close(0u32.into(), b"done");
+    trust_show(Some(server))
+}
+
+/// Drop the pin for `server`; the next connection pins afresh
+pub fn trust_forget(server: &str) -> Result<()> {
+    let mut known = known_servers::load()?;
+    if known.servers.remove(server).is_some() {
+        known_servers::save(&known)?;
+        println!("✅ Forgot {}", server);
+    } else {
+        println!("⚠️  {} was not pinned", server);
+    }
     Ok(())
 }
 
 async fn send_request(send: &mut quinn::SendStream, request: &Request) -> Result<()> {
-    let json = serde_json::to_vec(request)?;
+    let json = match crypt::active()? {
+        Some(cipher) => serde_json::to_vec(&cipher.seal_request(request)?)?,
+        None => serde_json::to_vec(request)?,
+    };
     let len = (json.len() as u32).to_be_bytes();
     send.write_all(&len).await?;
     send.write_all(&json).await?;
@@ -367,5 +1341,9 @@ async fn recv_response(recv: &mut quinn::RecvStream) -> Result<Response> {
     let mut buf = vec![0u8; len];
     recv.read_exact(&mut buf).await?;
     
-    Ok(serde_json::from_slice(&buf)?)
+    let response = serde_json::from_slice(&buf)?;
+    Ok(match crypt::active()? {
+        Some(cipher) => cipher.open_response(response),
+        None => response,
+    })
 }
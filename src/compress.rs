@@ -0,0 +1,126 @@
+//! Optional zstd compression of file bodies on the wire
+//!
+//! The sending side decides per transfer: files with an already compressed
+//! extension, or whose first block does not shrink noticeably, go out raw.
+//! The codec in use is named in the `Put` request or the `File` response;
+//! the body is then a single compressed frame up to the end of the stream.
+
+use std::io::Read;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, BufReader, ReadBuf};
+
+use crate::protocol::{Codec, Compression};
+
+const MAX_LEVEL: i32 = 19;
+
+/// Bytes from the start of a file used to judge whether compression pays off
+const SAMPLE_SIZE: u64 = 128 * 1024;
+
+/// Compress only if the sample shrinks to at most this fraction
+const MAX_RATIO: f64 = 0.9;
+
+/// Formats that are compressed already
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "apk", "avif", "br", "bz2", "deb", "docx", "flac", "gif", "gz", "heic", "jar", "jpeg", "jpg",
+    "lz4", "m4a", "mkv", "mov", "mp3", "mp4", "odt", "ogg", "pdf", "png", "pptx", "rar", "rpm", "tgz",
+    "txz", "webm", "webp", "xlsx", "xz", "zip", "zst",
+];
+
+/// Compression for sending `path` at `level`, or `None` if it is not worth it
+pub fn choose(path: &Path, level: Option<i32>) -> Option<Compression> {
+    let level = level?.clamp(1, MAX_LEVEL);
+    let extension = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+    if extension.is_some_and(|e| COMPRESSED_EXTENSIONS.contains(&e.as_str())) {
+        tracing::debug!("Not compressing {} (compressed format)", path.display());
+        return None;
+    }
+
+    let mut sample = Vec::new();
+    std::fs::File::open(path).ok()?.take(SAMPLE_SIZE).read_to_end(&mut sample).ok()?;
+    if sample.is_empty() {
+        return None;
+    }
+    let packed = zstd::bulk::compress(&sample, level).ok()?;
+    if packed.len() as f64 > sample.len() as f64 * MAX_RATIO {
+        tracing::debug!("Not compressing {} (no gain on sample)", path.display());
+        return None;
+    }
+    Some(Compression { codec: Codec::Zstd, level })
+}
+
+/// `reader` as it goes on the wire with `compression`
+pub fn encoder<'a, R>(reader: R, compression: Option<Compression>) -> Box<dyn AsyncRead + Unpin + Send + 'a>
+where
+    R: AsyncRead + Unpin + Send + 'a,
+{
+    match compression {
+        Some(Compression { codec: Codec::Zstd, level }) => {
+            let level = async_compression::Level::Precise(level.clamp(1, MAX_LEVEL));
+            Box::new(async_compression::tokio::bufread::ZstdEncoder::with_quality(BufReader::new(reader), level))
+        }
+        None => Box::new(reader),
+    }
+}
+
+/// Original bytes of a body sent with `compression`
+pub fn decoder<'a, R>(reader: R, compression: Option<Compression>) -> Box<dyn AsyncRead + Unpin + Send + 'a>
+where
+    R: AsyncRead + Unpin + Send + 'a,
+{
+    match compression {
+        Some(Compression { codec: Codec::Zstd, .. }) => {
+            Box::new(async_compression::tokio::bufread::ZstdDecoder::new(BufReader::new(reader)))
+        }
+        None => Box::new(reader),
+    }
+}
+
+/// File bytes moved and what they took on the wire
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Traffic {
+    pub bytes: u64,
+    pub wire: u64,
+}
+
+impl Traffic {
+    /// `, N on the wire, P% saved` if compression helped, else nothing
+    pub fn savings(&self) -> String {
+        if self.wire >= self.bytes {
+            return String::new();
+        }
+        let saved = 100.0 - self.wire as f64 * 100.0 / self.bytes as f64;
+        format!(", {} on the wire, {:.0}% saved", self.wire, saved)
+    }
+}
+
+impl std::ops::AddAssign for Traffic {
+    fn add_assign(&mut self, other: Self) {
+        self.bytes += other.bytes;
+        self.wire += other.wire;
+    }
+}
+
+/// Counts the bytes read through it
+pub struct Counted<R> {
+    inner: R,
+    pub count: u64,
+}
+
+impl<R> Counted<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Counted<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.count += (buf.filled().len() - before) as u64;
+        }
+        poll
+    }
+}
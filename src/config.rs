@@ -4,16 +4,39 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
+use crate::acl::AclRule;
+use crate::auth::TokenEntry;
+use crate::crypt::EncryptionConfig;
+use crate::versions::Retention;
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Config {
     pub server: ServerConfig,
     pub client: ClientConfig,
+    #[serde(default)]
+    pub tls: TlsConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub root: String,
     pub bind: String,
+    /// Hostnames/IPs put into the generated certificate (SANs)
+    #[serde(default = "default_names")]
+    pub names: Vec<String>,
+    /// Authorization rules (`[[server.acl]]`); none means allow everything
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub acl: Vec<AclRule>,
+    /// Accepted pre-shared tokens (`[[server.tokens]]`); if set, clients
+    /// without a certificate must authenticate with one of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<TokenEntry>,
+    /// Keep replaced and deleted files (`[server.versioning]`); off if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub versioning: Option<Retention>,
+    /// Store identical file contents only once (unix only)
+    #[serde(default)]
+    pub dedup: bool,
 }
 
 impl Default for ServerConfig {
@@ -21,19 +44,63 @@ impl Default for ServerConfig {
         Self {
             root: "/backup/incoming".to_string(),
             bind: "0.0.0.0:4433".to_string(),
+            names: default_names(),
+            acl: Vec::new(),
+            tokens: Vec::new(),
+            versioning: None,
+            dedup: false,
         }
     }
 }
 
+fn default_names() -> Vec<String> {
+    vec!["localhost".to_string()]
+}
+
+/// Certificate files (PEM). Without `cert`/`key` the server persists a
+/// self-signed pair in the config directory; without `ca` the client pins
+/// server certificates on first use.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct TlsConfig {
+    /// Server certificate chain (leaf first)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+    /// CA bundle the client verifies server certificates against
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca: Option<PathBuf>,
+    /// Server: require client certificates signed by this CA (mTLS)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ca: Option<PathBuf>,
+    /// Client: certificate chain and key presented to the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<PathBuf>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClientConfig {
     pub default_server: String,
+    /// Name to verify the server certificate against (default: host part of the address)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    /// Pre-shared token presented to the server (`HANK_SYNC_TOKEN` overrides)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Encrypt files before upload (`[client.encryption]`); off if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionConfig>,
 }
 
 impl Default for ClientConfig {
     fn default() -> Self {
         Self {
             default_server: "192.168.178.20:4433".to_string(),
+            server_name: None,
+            token: None,
+            encryption: None,
         }
     }
 }
@@ -49,6 +116,14 @@ pub fn config_dir() -> Result<PathBuf> {
     default_config_dir()
 }
 
+/// Per-user directory for temporary files
+pub fn cache_dir() -> Result<PathBuf> {
+    Ok(directories::ProjectDirs::from("", "", "hank-sync")
+        .ok_or_else(|| anyhow::anyhow!("Could not determine cache directory"))?
+        .cache_dir()
+        .to_path_buf())
+}
+
 pub fn resolve_server(override_server: Option<String>) -> Result<String> {
     if let Some(server) = override_server {
         return Ok(server);
@@ -74,6 +149,19 @@ pub fn load(config_dir: Option<&Path>) -> Result<Config> {
     Ok(config)
 }
 
+/// Like `load`, but a missing config file yields the defaults
+pub fn load_or_default(config_dir: Option<&Path>) -> Result<Config> {
+    let dir = match config_dir {
+        Some(d) => d.to_path_buf(),
+        None => default_config_dir()?,
+    };
+
+    if !dir.join("config.toml").exists() {
+        return Ok(Config::default());
+    }
+    load(Some(&dir))
+}
+
 pub fn init(config_dir: Option<&Path>) -> Result<()> {
     let dir = match config_dir {
         Some(d) => d.to_path_buf(),
@@ -0,0 +1,524 @@
+//! Client-side end-to-end encryption
+//!
+//! With `[client.encryption]` configured, file contents are encrypted before
+//! they leave the client and decrypted after they arrive, so the server only
+//! ever stores ciphertext (with `names = true` also for file names).
+//!
+//! Content format: a header (`MAGIC`, key id and file id), then chunks of up
+//! to `CHUNK` plaintext bytes, each sealed with XChaCha20-Poly1305 as
+//! `nonce || ciphertext || tag`. The file id, chunk index and a last-chunk
+//! flag are authenticated, so reordered or truncated files fail to decrypt,
+//! and so do chunks moved over from another file.
+//!
+//! The file id is derived from the key and the remote path the file is
+//! uploaded to. Nonces are derived from the key, the file id and the chunk
+//! itself: the same content at the same path always encrypts to the same
+//! bytes. That keeps hash checks, the identical skip, resume and delta
+//! uploads working on ciphertext, at the price of revealing when a file (or
+//! a chunk of it) has not changed. Whole files are not bound to where they
+//! are: the server can still swap two of them, or serve an older version of
+//! one, without that being detected.
+//!
+//! Names are encrypted per path component (deterministic SIV over keyed
+//! blake3) and hex encoded.
+
+use anyhow::{Context, Result};
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config;
+use crate::protocol::{Request, Response};
+
+const MAGIC: &[u8; 4] = b"HSE1";
+const KEY_ID_LEN: usize = 8;
+const FILE_ID_LEN: usize = 16;
+pub const HEADER_LEN: usize = MAGIC.len() + KEY_ID_LEN + FILE_ID_LEN;
+
+/// Which file a chunk belongs to
+pub type FileId = [u8; FILE_ID_LEN];
+
+/// Plaintext bytes per chunk
+pub const CHUNK: usize = 64 * 1024;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+/// Size of a full chunk on the server
+pub const SEALED_CHUNK: usize = CHUNK + NONCE_LEN + TAG_LEN;
+
+const SIV_LEN: usize = 16;
+/// Longest name component the file system on the server will still take
+const MAX_NAME: usize = 255;
+
+/// `[client.encryption]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// 32-byte key, hex encoded (`hank-sync keygen`)
+    pub key: String,
+    /// Encrypt file and directory names too
+    #[serde(default)]
+    pub names: bool,
+}
+
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+    nonce_key: [u8; 32],
+    file_id_key: [u8; 32],
+    name_mac_key: [u8; 32],
+    name_stream_key: [u8; 32],
+    key_id: [u8; KEY_ID_LEN],
+    names: bool,
+}
+
+static ACTIVE: OnceLock<Option<Cipher>> = OnceLock::new();
+
+/// The cipher from the client config, if encryption is configured
+pub fn active() -> Result<Option<&'static Cipher>> {
+    if let Some(cipher) = ACTIVE.get() {
+        return Ok(cipher.as_ref());
+    }
+    let config = config::load_or_default(None)?;
+    let cipher = config.client.encryption.as_ref().map(Cipher::new).transpose()?;
+    Ok(ACTIVE.get_or_init(|| cipher).as_ref())
+}
+
+/// A fresh random key, hex encoded
+pub fn generate_key() -> String {
+    to_hex(&XChaCha20Poly1305::generate_key(&mut OsRng))
+}
+
+impl Cipher {
+    pub fn new(config: &EncryptionConfig) -> Result<Self> {
+        let key = blake3::Hash::from_hex(config.key.trim())
+            .map_err(|_| anyhow::anyhow!("Invalid encryption key: expected 64 hex characters (see 'hank-sync keygen')"))?;
+        let key = key.as_bytes();
+        let mut key_id = [0u8; KEY_ID_LEN];
+        key_id.copy_from_slice(&blake3::derive_key("hank-sync key id", key)[..KEY_ID_LEN]);
+
+        Ok(Self {
+            aead: XChaCha20Poly1305::new(&blake3::derive_key("hank-sync content", key).into()),
+            nonce_key: blake3::derive_key("hank-sync content nonce", key),
+            file_id_key: blake3::derive_key("hank-sync file id", key),
+            name_mac_key: blake3::derive_key("hank-sync name mac", key),
+            name_stream_key: blake3::derive_key("hank-sync name stream", key),
+            key_id,
+            names: config.names,
+        })
+    }
+
+    /// Short fingerprint of the key, stored in every file header
+    pub fn key_id(&self) -> String {
+        to_hex(&self.key_id)
+    }
+
+    /// Size on the server of `plain` bytes of content
+    pub fn sealed_len(plain: u64) -> u64 {
+        let chunks = plain.div_ceil(CHUNK as u64).max(1);
+        HEADER_LEN as u64 + chunks * (SEALED_CHUNK - CHUNK) as u64 + plain
+    }
+
+    /// Content size of a file of `sealed` bytes on the server, if that is a
+    /// possible size for an encrypted file
+    pub fn plain_len(sealed: u64) -> Option<u64> {
+        let body = sealed.checked_sub(HEADER_LEN as u64)?;
+        let chunks = body.div_ceil(SEALED_CHUNK as u64).max(1);
+        let plain = body.checked_sub(chunks * (SEALED_CHUNK - CHUNK) as u64)?;
+        (Self::sealed_len(plain) == sealed).then_some(plain)
+    }
+
+    /// Number of chunks in a file of `sealed` bytes
+    pub fn chunks(sealed: u64) -> u64 {
+        (sealed.saturating_sub(HEADER_LEN as u64)).div_ceil(SEALED_CHUNK as u64).max(1)
+    }
+
+    /// File id for content uploaded to `remote_path`
+    pub fn file_id(&self, remote_path: &str) -> FileId {
+        // Spelling variants of the same path (`/a//b`, `a/./b`) get the same id
+        let path: Vec<&str> = remote_path.split('/').filter(|p| !p.is_empty() && *p != ".").collect();
+        let mut id = [0u8; FILE_ID_LEN];
+        id.copy_from_slice(&blake3::keyed_hash(&self.file_id_key, path.join("/").as_bytes()).as_bytes()[..FILE_ID_LEN]);
+        id
+    }
+
+    fn header(&self, file: &FileId) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[MAGIC.len()..MAGIC.len() + KEY_ID_LEN].copy_from_slice(&self.key_id);
+        header[MAGIC.len() + KEY_ID_LEN..].copy_from_slice(file);
+        header
+    }
+
+    /// Check that `header` starts a file encrypted with our key and return
+    /// the file id its chunks are bound to
+    pub fn check_header(&self, header: &[u8]) -> Result<FileId> {
+        if header.len() < HEADER_LEN || &header[..MAGIC.len()] != MAGIC {
+            anyhow::bail!("Not encrypted (was it uploaded without [client.encryption]?)");
+        }
+        let key_id = &header[MAGIC.len()..MAGIC.len() + KEY_ID_LEN];
+        if key_id != self.key_id {
+            anyhow::bail!(
+                "Encrypted with a different key (key id {}, the configured key is {})",
+                to_hex(key_id),
+                self.key_id()
+            );
+        }
+        let mut file = [0u8; FILE_ID_LEN];
+        file.copy_from_slice(&header[MAGIC.len() + KEY_ID_LEN..HEADER_LEN]);
+        Ok(file)
+    }
+
+    fn seal_chunk(&self, file: &FileId, index: u64, last: bool, plain: &[u8]) -> Vec<u8> {
+        let aad = chunk_aad(file, index, last);
+        let mut hasher = blake3::Hasher::new_keyed(&self.nonce_key);
+        hasher.update(&aad).update(plain);
+        let mut nonce = [0u8; NONCE_LEN];
+        hasher.finalize_xof().fill(&mut nonce);
+
+        let sealed = self.aead
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: plain, aad: &aad })
+            .expect("XChaCha20-Poly1305 encryption does not fail");
+        [&nonce[..], &sealed].concat()
+    }
+
+    /// Decrypt chunk `index` of `file` (`last` if it is the final one)
+    pub fn open_chunk(&self, file: &FileId, index: u64, last: bool, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            anyhow::bail!("Encrypted data is truncated");
+        }
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        let aad = chunk_aad(file, index, last);
+        self.aead
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: data, aad: &aad })
+            .map_err(|_| anyhow::anyhow!("Decryption failed at chunk {}: data is corrupted or was tampered with", index))
+    }
+
+    /// Encrypt everything from `input` into `output` as `file`
+    pub fn seal(&self, file: &FileId, mut input: impl Read, mut output: impl Write) -> Result<()> {
+        output.write_all(&self.header(file))?;
+        let mut current = read_chunk(&mut input, CHUNK)?;
+        for index in 0.. {
+            // A full chunk may still be the last one, look ahead to find out
+            let next = if current.len() == CHUNK { read_chunk(&mut input, CHUNK)? } else { Vec::new() };
+            let last = next.is_empty();
+            output.write_all(&self.seal_chunk(file, index, last, &current))?;
+            if last {
+                break;
+            }
+            current = next;
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Decrypt everything from `input` (a complete encrypted file) into `output`
+    pub fn open(&self, mut input: impl Read, mut output: impl Write) -> Result<()> {
+        let file = self.check_header(&read_chunk(&mut input, HEADER_LEN)?)?;
+
+        let mut current = read_chunk(&mut input, SEALED_CHUNK)?;
+        for index in 0.. {
+            let next = if current.len() == SEALED_CHUNK { read_chunk(&mut input, SEALED_CHUNK)? } else { Vec::new() };
+            let last = next.is_empty();
+            output.write_all(&self.open_chunk(&file, index, last, &current)?)?;
+            if last {
+                break;
+            }
+            current = next;
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    /// blake3 of what `path` looks like encrypted for `remote_path`, as the
+    /// server would report it
+    pub async fn hash_sealed(&'static self, path: &Path, remote_path: &str) -> Result<String> {
+        let path = path.to_path_buf();
+        let file = self.file_id(remote_path);
+        tokio::task::spawn_blocking(move || {
+            let mut hasher = blake3::Hasher::new();
+            self.seal(&file, std::io::BufReader::new(std::fs::File::open(&path)?), &mut hasher)?;
+            Ok(hasher.finalize().to_hex().to_string())
+        })
+        .await?
+    }
+
+    /// Encrypted copy of `path` for `remote_path` in a temporary file
+    pub async fn seal_file(&'static self, path: &Path, remote_path: &str) -> Result<SealedFile> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        // Not the shared temp directory: other users must not get to pick what we write to
+        let dir = config::cache_dir()?;
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Cannot create {}", dir.display()))?;
+        let temp = dir.join(format!(
+            "{}-{}.sealed",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let output = create_new(&temp, 0o600)
+            .with_context(|| format!("Cannot create {}", temp.display()))?;
+        let sealed = SealedFile { path: temp };
+        let src = path.to_path_buf();
+        let file = self.file_id(remote_path);
+        tokio::task::spawn_blocking(move || {
+            let input = std::io::BufReader::new(std::fs::File::open(&src)?);
+            self.seal(&file, input, std::io::BufWriter::new(output))
+        })
+        .await?
+        .with_context(|| format!("Cannot encrypt {}", path.display()))?;
+        Ok(sealed)
+    }
+
+    /// Decrypt the encrypted file `src` into `dest`, which must not exist
+    pub async fn open_file(&'static self, src: &Path, dest: &Path) -> Result<()> {
+        let (src, dest) = (src.to_path_buf(), dest.to_path_buf());
+        tokio::task::spawn_blocking(move || {
+            let input = std::io::BufReader::new(std::fs::File::open(&src)?);
+            let output = create_new(&dest, 0o666)
+                .with_context(|| format!("Cannot create {}", dest.display()))?;
+            let result = self.open(input, std::io::BufWriter::new(output));
+            if result.is_err() {
+                let _ = std::fs::remove_file(&dest);
+            }
+            result
+        })
+        .await?
+    }
+
+    fn encrypt_name(&self, name: &str) -> Result<String> {
+        let siv = blake3::keyed_hash(&self.name_mac_key, name.as_bytes());
+        let siv = &siv.as_bytes()[..SIV_LEN];
+        let mut data = name.as_bytes().to_vec();
+        self.name_stream(siv, &mut data);
+
+        let encrypted = to_hex(&[siv, &data].concat());
+        if encrypted.len() > MAX_NAME {
+            anyhow::bail!("Name too long to encrypt (at most {} bytes): {}", (MAX_NAME - 2 * SIV_LEN) / 2, name);
+        }
+        Ok(encrypted)
+    }
+
+    fn decrypt_name(&self, encrypted: &str) -> Option<String> {
+        let bytes = from_hex(encrypted)?;
+        if bytes.len() < SIV_LEN {
+            return None;
+        }
+        let (siv, data) = bytes.split_at(SIV_LEN);
+        let mut data = data.to_vec();
+        self.name_stream(siv, &mut data);
+        let expected = blake3::keyed_hash(&self.name_mac_key, &data);
+        if expected.as_bytes()[..SIV_LEN] != *siv {
+            return None;
+        }
+        String::from_utf8(data).ok()
+    }
+
+    fn name_stream(&self, siv: &[u8], data: &mut [u8]) {
+        let mut keystream = vec![0u8; data.len()];
+        blake3::Hasher::new_keyed(&self.name_stream_key).update(siv).finalize_xof().fill(&mut keystream);
+        data.iter_mut().zip(keystream).for_each(|(b, k)| *b ^= k);
+    }
+
+    /// Encrypt each component of a `/`-separated path (`.` and `..` stay)
+    pub fn encrypt_path(&self, path: &str) -> Result<String> {
+        let parts: Result<Vec<String>> = path
+            .split('/')
+            .map(|part| match part {
+                "" | "." | ".." => Ok(part.to_string()),
+                name => self.encrypt_name(name),
+            })
+            .collect();
+        Ok(parts?.join("/"))
+    }
+
+    /// Reverse of `encrypt_path`; components that do not decrypt are kept
+    pub fn decrypt_path(&self, path: &str) -> String {
+        path.split('/')
+            .map(|part| self.decrypt_name(part).unwrap_or_else(|| part.to_string()))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// `request` as it goes to the server (names encrypted if configured)
+    pub fn seal_request(&self, request: &Request) -> Result<Request> {
+        let mut request = request.clone();
+        if !self.names {
+            return Ok(request);
+        }
+        for path in request.paths_mut() {
+            *path = self.encrypt_path(path)?;
+        }
+        if let Request::Put { meta: Some(meta), .. } = &mut request
+            && let Some(target) = &mut meta.symlink
+        {
+            *target = self.encrypt_path(target)?;
+        }
+        Ok(request)
+    }
+
+    /// `response` as the rest of the client expects it: plaintext names and
+    /// content sizes. Transfer sizes (`File`) stay as they are.
+    pub fn open_response(&self, mut response: Response) -> Response {
+        let plain_len = |size: u64| Self::plain_len(size).unwrap_or(size);
+        match &mut response {
+            Response::List { entries } => {
+                for entry in entries {
+                    if self.names {
+                        entry.name = self.decrypt_path(&entry.name);
+                    }
+                    if !entry.is_dir {
+                        entry.size = plain_len(entry.size);
+                    }
+                }
+            }
+            Response::Manifest { entries } => {
+                for entry in entries {
+                    if self.names {
+                        entry.path = self.decrypt_path(&entry.path);
+                    }
+                    entry.size = plain_len(entry.size);
+                }
+            }
+            Response::Versions { versions } => {
+                for version in versions {
+                    version.size = plain_len(version.size);
+                }
+            }
+            Response::File { meta: Some(meta), .. } if self.names => {
+                if let Some(target) = &mut meta.symlink {
+                    *target = self.decrypt_path(target);
+                }
+            }
+            _ => {}
+        }
+        response
+    }
+}
+
+/// Temporary encrypted copy of a file, removed when dropped
+pub struct SealedFile {
+    path: PathBuf,
+}
+
+impl SealedFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SealedFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn chunk_aad(file: &FileId, index: u64, last: bool) -> [u8; FILE_ID_LEN + 9] {
+    let mut aad = [0u8; FILE_ID_LEN + 9];
+    aad[..FILE_ID_LEN].copy_from_slice(file);
+    aad[FILE_ID_LEN..FILE_ID_LEN + 8].copy_from_slice(&index.to_le_bytes());
+    aad[FILE_ID_LEN + 8] = last as u8;
+    aad
+}
+
+/// Open `path` for writing only if nothing (not even a symlink) is there yet
+fn create_new(path: &Path, mode: u32) -> std::io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    #[cfg(not(unix))]
+    let _ = mode;
+    options.open(path)
+}
+
+/// Read up to `len` bytes, fewer only at the end of `input`
+fn read_chunk(input: &mut impl Read, len: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(len);
+    input.take(len as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(key: &str, names: bool) -> Cipher {
+        Cipher::new(&EncryptionConfig { key: key.repeat(64), names }).unwrap()
+    }
+
+    #[test]
+    fn round_trips_across_chunk_boundaries() {
+        let cipher = cipher("a", false);
+        for len in [0, 1, CHUNK - 1, CHUNK, CHUNK + 1, 3 * CHUNK] {
+            let plain: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let file = cipher.file_id("dir/file");
+            let mut sealed = Vec::new();
+            cipher.seal(&file, &plain[..], &mut sealed).unwrap();
+            assert_eq!(sealed.len() as u64, Cipher::sealed_len(len as u64), "{}", len);
+            assert_eq!(Cipher::plain_len(sealed.len() as u64), Some(len as u64), "{}", len);
+
+            let mut again = Vec::new();
+            cipher.seal(&cipher.file_id("/dir//./file"), &plain[..], &mut again).unwrap();
+            assert_eq!(sealed, again, "deterministic");
+
+            let mut opened = Vec::new();
+            cipher.open(&sealed[..], &mut opened).unwrap();
+            assert_eq!(opened, plain, "{}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_key_and_tampering() {
+        let plain = vec![7u8; 2 * CHUNK + 10];
+        let file = cipher("a", false).file_id("file");
+        let mut sealed = Vec::new();
+        cipher("a", false).seal(&file, &plain[..], &mut sealed).unwrap();
+
+        let wrong = cipher("b", false).open(&sealed[..], std::io::sink()).unwrap_err();
+        assert!(wrong.to_string().contains("different key"), "{}", wrong);
+        let plain_input = cipher("a", false).open(&plain[..], std::io::sink()).unwrap_err();
+        assert!(plain_input.to_string().contains("Not encrypted"), "{}", plain_input);
+
+        // Dropping the last chunk leaves a full chunk not marked as last
+        let truncated = &sealed[..HEADER_LEN + 2 * SEALED_CHUNK];
+        assert!(cipher("a", false).open(truncated, std::io::sink()).is_err());
+        let mut flipped = sealed.clone();
+        flipped[HEADER_LEN + SEALED_CHUNK + 100] ^= 1;
+        assert!(cipher("a", false).open(&flipped[..], std::io::sink()).is_err());
+
+        // A chunk from another file with the same content does not fit
+        let mut other = Vec::new();
+        cipher("a", false).seal(&cipher("a", false).file_id("other"), &plain[..], &mut other).unwrap();
+        let mut swapped = sealed.clone();
+        swapped[HEADER_LEN..HEADER_LEN + SEALED_CHUNK].copy_from_slice(&other[HEADER_LEN..HEADER_LEN + SEALED_CHUNK]);
+        assert!(cipher("a", false).open(&swapped[..], std::io::sink()).is_err());
+    }
+
+    #[test]
+    fn encrypts_names_per_component() {
+        let cipher = cipher("c", true);
+        let encrypted = cipher.encrypt_path("/backup/2024/../notes.txt").unwrap();
+        assert!(encrypted.starts_with('/') && encrypted.contains("/../"));
+        assert!(!encrypted.contains("backup") && !encrypted.contains("notes"));
+        assert_eq!(encrypted, cipher.encrypt_path("/backup/2024/../notes.txt").unwrap());
+        assert_eq!(cipher.decrypt_path(&encrypted), "/backup/2024/../notes.txt");
+        // Names from elsewhere come back unchanged
+        assert_eq!(cipher.decrypt_path("plain/name"), "plain/name");
+        assert!(cipher.encrypt_path(&"x".repeat(200)).is_err());
+    }
+}
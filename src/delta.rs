@@ -0,0 +1,327 @@
+//! Delta encoding with rsync-style rolling checksums
+//!
+//! The receiver describes its copy of a file as a list of fixed-size block
+//! signatures (weak rolling checksum + truncated blake3). The sender slides a
+//! window over its version, emits `Copy` ops for blocks the receiver already
+//! has and `Literal` ops for everything else.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::protocol::BlockSignature;
+
+const MIN_BLOCK_SIZE: u32 = 4 * 1024;
+const MAX_BLOCK_SIZE: u32 = 1024 * 1024;
+
+/// Literals are flushed in pieces of at most this size
+const MAX_LITERAL: usize = 64 * 1024;
+
+/// Upper bound accepted when decoding a literal from the wire
+const MAX_WIRE_LITERAL: u32 = 4 * 1024 * 1024;
+
+const OP_COPY: u8 = 0;
+const OP_LITERAL: u8 = 1;
+const OP_END: u8 = 2;
+
+#[derive(Debug)]
+pub enum Op {
+    /// Copy block `n` of the receiver's existing file
+    Copy(u32),
+    /// New bytes
+    Literal(Vec<u8>),
+}
+
+/// Block size for a file of `size` bytes (~sqrt(size), like rsync)
+pub fn block_size_for(size: u64) -> u32 {
+    ((size as f64).sqrt() as u32)
+        .next_multiple_of(1024)
+        .clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+}
+
+/// Strong block hash: first 16 bytes of blake3, hex encoded
+pub fn strong_hash(data: &[u8]) -> String {
+    blake3::hash(data).to_hex()[..32].to_string()
+}
+
+/// rsync weak checksum that can be rolled one byte at a time
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut a = 0u32;
+        let mut b = 0u32;
+        for (i, &x) in window.iter().enumerate() {
+            a = a.wrapping_add(x as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(x as u32));
+        }
+        Self { a, b, len }
+    }
+
+    fn roll(&mut self, out: u8, inp: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inp as u32);
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32)).wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+/// Compute block signatures of everything `reader` yields
+pub fn signature<R: Read>(mut reader: R, block_size: u32) -> io::Result<Vec<BlockSignature>> {
+    let mut blocks = Vec::new();
+    let mut buf = vec![0u8; block_size as usize];
+    loop {
+        let n = read_full(&mut reader, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        blocks.push(BlockSignature {
+            weak: Rolling::new(&buf[..n]).digest(),
+            strong: strong_hash(&buf[..n]),
+        });
+        if n < buf.len() {
+            break;
+        }
+    }
+    Ok(blocks)
+}
+
+/// Walk `reader` and emit the ops that rebuild it from a file with `blocks`
+pub fn diff<R, F>(mut reader: R, block_size: u32, blocks: &[BlockSignature], mut emit: F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(Op) -> io::Result<()>,
+{
+    let bs = block_size as usize;
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, block) in blocks.iter().enumerate() {
+        index.entry(block.weak).or_default().push(i);
+    }
+
+    // buf[lit_start..pos] is pending literal data, buf[pos..pos + bs] the window
+    let mut buf: Vec<u8> = Vec::new();
+    let mut pos = 0usize;
+    let mut lit_start = 0usize;
+    let mut eof = false;
+    let mut rolling: Option<Rolling> = None;
+    let mut pending_out: Option<u8> = None;
+    let mut chunk = vec![0u8; 64 * 1024];
+
+    loop {
+        if lit_start >= MAX_BLOCK_SIZE as usize {
+            buf.drain(..lit_start);
+            pos -= lit_start;
+            lit_start = 0;
+        }
+        while !eof && buf.len() < pos + bs {
+            let n = reader.read(&mut chunk)?;
+            if n == 0 {
+                eof = true;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        if buf.len() < pos + bs {
+            break;
+        }
+
+        let window = &buf[pos..pos + bs];
+        let weak = match (rolling.as_mut(), pending_out.take()) {
+            (Some(r), Some(out)) => {
+                r.roll(out, window[bs - 1]);
+                r.digest()
+            }
+            _ => {
+                let r = Rolling::new(window);
+                let digest = r.digest();
+                rolling = Some(r);
+                digest
+            }
+        };
+
+        let matched = index.get(&weak).and_then(|candidates| {
+            let strong = strong_hash(window);
+            candidates.iter().copied().find(|&i| blocks[i].strong == strong)
+        });
+
+        match matched {
+            Some(i) => {
+                if lit_start < pos {
+                    emit(Op::Literal(buf[lit_start..pos].to_vec()))?;
+                }
+                emit(Op::Copy(i as u32))?;
+                pos += bs;
+                lit_start = pos;
+                rolling = None;
+            }
+            None => {
+                pending_out = Some(buf[pos]);
+                pos += 1;
+                if pos - lit_start >= MAX_LITERAL {
+                    emit(Op::Literal(buf[lit_start..pos].to_vec()))?;
+                    lit_start = pos;
+                }
+            }
+        }
+    }
+
+    // Whatever is left (including a short tail) goes out as literal data
+    for piece in buf[lit_start..].chunks(MAX_LITERAL) {
+        emit(Op::Literal(piece.to_vec()))?;
+    }
+    Ok(())
+}
+
+/// Encode one op on the wire
+pub async fn write_op<W: AsyncWrite + Unpin>(w: &mut W, op: &Op) -> Result<()> {
+    match op {
+        Op::Copy(i) => {
+            w.write_u8(OP_COPY).await?;
+            w.write_u32(*i).await?;
+        }
+        Op::Literal(data) => {
+            w.write_u8(OP_LITERAL).await?;
+            w.write_u32(data.len() as u32).await?;
+            w.write_all(data).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Mark the end of the op stream
+pub async fn write_end<W: AsyncWrite + Unpin>(w: &mut W) -> Result<()> {
+    w.write_u8(OP_END).await?;
+    Ok(())
+}
+
+/// Decode the next op, `None` at the end marker
+pub async fn read_op<R: AsyncRead + Unpin>(r: &mut R) -> Result<Option<Op>> {
+    match r.read_u8().await? {
+        OP_COPY => Ok(Some(Op::Copy(r.read_u32().await?))),
+        OP_LITERAL => {
+            let len = r.read_u32().await?;
+            if len > MAX_WIRE_LITERAL {
+                anyhow::bail!("Literal too large: {} bytes", len);
+            }
+            let mut data = vec![0u8; len as usize];
+            r.read_exact(&mut data).await?;
+            Ok(Some(Op::Literal(data)))
+        }
+        OP_END => Ok(None),
+        other => anyhow::bail!("Unknown delta op: {}", other),
+    }
+}
+
+/// Like `read_exact`, but returns the short count at end of input
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ops that turn `base` into `new`, with the receiver's block size
+    fn ops(base: &[u8], new: &[u8]) -> (u32, Vec<Op>) {
+        let block_size = block_size_for(base.len() as u64);
+        let blocks = signature(base, block_size).unwrap();
+        let mut ops = Vec::new();
+        diff(new, block_size, &blocks, |op| {
+            ops.push(op);
+            Ok(())
+        }).unwrap();
+        (block_size, ops)
+    }
+
+    /// Rebuild a file the way the server does
+    fn apply(base: &[u8], block_size: u32, ops: &[Op]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for op in ops {
+            match op {
+                Op::Copy(i) => {
+                    let start = *i as usize * block_size as usize;
+                    let end = (start + block_size as usize).min(base.len());
+                    out.extend_from_slice(&base[start..end]);
+                }
+                Op::Literal(data) => out.extend_from_slice(data),
+            }
+        }
+        out
+    }
+
+    fn literal_bytes(ops: &[Op]) -> usize {
+        ops.iter().map(|op| match op {
+            Op::Literal(data) => data.len(),
+            Op::Copy(_) => 0,
+        }).sum()
+    }
+
+    /// Deterministic bytes without repeating blocks
+    fn data(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        (0..len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        }).collect()
+    }
+
+    #[test]
+    fn round_trips_unchanged_file() {
+        let base = data(100_000, 1);
+        let (block_size, ops) = ops(&base, &base);
+        assert_eq!(apply(&base, block_size, &ops), base);
+        // Only the short tail is sent again
+        assert_eq!(literal_bytes(&ops), base.len() % block_size as usize);
+    }
+
+    #[test]
+    fn finds_blocks_after_an_insertion() {
+        let base = data(100_000, 2);
+        let mut new = base.clone();
+        new.splice(10_000..10_000, b"inserted".iter().copied());
+        let (block_size, ops) = ops(&base, &new);
+        assert_eq!(apply(&base, block_size, &ops), new);
+        // Only the block around the insertion is sent again
+        assert!(literal_bytes(&ops) < 2 * block_size as usize, "{}", literal_bytes(&ops));
+    }
+
+    #[test]
+    fn handles_a_short_last_block() {
+        let base = data(3 * 4096 + 100, 3);
+        let blocks = signature(&base[..], 4096).unwrap();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[3].strong, strong_hash(&base[3 * 4096..]));
+
+        let mut new = base.clone();
+        new[5] ^= 0xff;
+        let (block_size, ops) = ops(&base, &new);
+        assert_eq!(apply(&base, block_size, &ops), new);
+        assert!(literal_bytes(&ops) <= block_size as usize + 100);
+    }
+
+    #[test]
+    fn sends_everything_against_an_empty_base() {
+        assert!(signature(&b""[..], 4096).unwrap().is_empty());
+        let new = data(200_000, 4);
+        let (block_size, ops) = ops(b"", &new);
+        assert_eq!(apply(b"", block_size, &ops), new);
+        assert_eq!(literal_bytes(&ops), new.len());
+        assert!(ops.iter().all(|op| matches!(op, Op::Literal(d) if d.len() <= MAX_LITERAL)));
+    }
+}
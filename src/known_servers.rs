@@ -0,0 +1,58 @@
+//! Pinned server certificate fingerprints (trust on first use)
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct KnownServers {
+    /// Server address (as given on the command line) → pinned certificate
+    pub servers: BTreeMap<String, Pin>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Pin {
+    pub fingerprint: String,
+    pub pinned_at: DateTime<Local>,
+}
+
+impl Pin {
+    pub fn new(fingerprint: String) -> Self {
+        Self { fingerprint, pinned_at: Local::now() }
+    }
+}
+
+fn known_servers_path() -> Result<PathBuf> {
+    let dir = crate::config::config_dir()?;
+    Ok(dir.join("known_servers.json"))
+}
+
+pub fn load() -> Result<KnownServers> {
+    let path = known_servers_path()?;
+    if !path.exists() {
+        return Ok(KnownServers::default());
+    }
+    let content = std::fs::read_to_string(path)?;
+    let known: KnownServers = serde_json::from_str(&content)?;
+    Ok(known)
+}
+
+pub fn save(known: &KnownServers) -> Result<()> {
+    let path = known_servers_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let content = serde_json::to_string_pretty(known)?;
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// SHA-256 fingerprint of a DER certificate: `sha256:<hex>`
+pub fn fingerprint(cert_der: &[u8]) -> String {
+    let digest = Sha256::digest(cert_der);
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
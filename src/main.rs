@@ -8,6 +8,17 @@ mod tls;
 mod config;
 mod audit;
 mod state;
+mod delta;
+mod sandbox;
+mod known_servers;
+mod acl;
+mod auth;
+mod sync;
+mod versions;
+mod objects;
+mod compress;
+mod crypt;
+mod progress;
 
 #[derive(Parser)]
 #[command(name = "hank-sync")]
@@ -16,6 +27,10 @@ struct Cli {
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
+
+    /// How to show transfer progress (on stderr)
+    #[arg(long, value_enum, default_value_t = progress::Mode::Auto)]
+    progress: progress::Mode,
     
     #[command(subcommand)]
     command: Commands,
@@ -50,6 +65,38 @@ enum Commands {
         /// Destination path on server (relative to root)
         #[arg(short, long)]
         dest: Option<String>,
+        
+        /// Send only changed blocks if the file already exists on the server
+        #[arg(long)]
+        delta: bool,
+        
+        /// Delete remote files and directories that no longer exist locally
+        #[arg(long)]
+        mirror: bool,
+        
+        /// Abort a mirror run that would delete more than this many entries
+        #[arg(long, default_value_t = 100, requires = "mirror")]
+        max_delete: usize,
+        
+        /// Only show what would be sent (and deleted)
+        #[arg(long)]
+        dry_run: bool,
+        
+        /// Skip unchanged files by blake3 instead of size + mtime
+        #[arg(long)]
+        checksum: bool,
+        
+        /// Number of files of a directory uploaded in parallel
+        #[arg(short, long, default_value_t = 4)]
+        jobs: usize,
+        
+        /// Keep mtime, permissions and symlinks
+        #[arg(short, long)]
+        preserve: bool,
+        
+        /// Compress with zstd on the wire (optional level 1-19, default 3)
+        #[arg(short = 'z', long, num_args = 0..=1, require_equals = true, default_missing_value = "3", value_name = "LEVEL")]
+        compress: Option<i32>,
     },
     
     /// List files on server
@@ -107,22 +154,131 @@ enum Commands {
 
         /// File path to view
         path: String,
+
+        /// Start at this byte offset
+        #[arg(long, default_value_t = 0)]
+        offset: u64,
+
+        /// Show at most this many bytes
+        #[arg(long)]
+        length: Option<u64>,
+
+        /// Show only the last N bytes
+        #[arg(long, conflicts_with = "offset")]
+        tail: Option<u64>,
+
+        /// Compress with zstd on the wire (optional level 1-19, default 3)
+        #[arg(short = 'z', long, num_args = 0..=1, require_equals = true, default_missing_value = "3", value_name = "LEVEL")]
+        compress: Option<i32>,
     },
     
-    /// Get (download) a file from server
+    /// Get (download) a file or directory from server (resumes partial downloads)
     Get {
         /// Server address (overrides config)
         #[arg(short, long)]
         server: Option<String>,
 
-        /// File path on server
+        /// File or directory path on server
         path: String,
 
         /// Destination path on client (file or directory)
         #[arg(short, long)]
         dest: Option<PathBuf>,
+
+        /// Number of files of a directory downloaded in parallel
+        #[arg(short, long, default_value_t = 4)]
+        jobs: usize,
+
+        /// Apply mtime, permissions and symlinks from the server
+        #[arg(short, long)]
+        preserve: bool,
+
+        /// Let the server compress with zstd (optional level 1-19, default 3)
+        #[arg(short = 'z', long, num_args = 0..=1, require_equals = true, default_missing_value = "3", value_name = "LEVEL")]
+        compress: Option<i32>,
     },
 
+    /// Two-way sync of a local directory with a directory on the server
+    Sync {
+        /// Server address (overrides config)
+        #[arg(short, long)]
+        server: Option<String>,
+        
+        /// Local directory
+        local: PathBuf,
+        
+        /// Directory on server (default: name of the local directory, relative to cwd)
+        remote: Option<String>,
+        
+        /// Compare blake3 hashes when both sides changed
+        #[arg(long)]
+        checksum: bool,
+        
+        /// Only show what would be transferred or deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    
+    /// Delete a file or directory on the server (relative to cwd)
+    Rm {
+        /// Server address (overrides config)
+        #[arg(short, long)]
+        server: Option<String>,
+        
+        /// File or directory on server
+        path: String,
+        
+        /// Delete directories with everything in them
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    
+    /// Move or rename a file or directory on the server (relative to cwd)
+    Mv {
+        /// Server address (overrides config)
+        #[arg(short, long)]
+        server: Option<String>,
+        
+        /// Existing file or directory
+        from: String,
+        
+        /// New path, or an existing directory to move into
+        to: String,
+    },
+    
+    /// Create a directory on the server (relative to cwd)
+    Mkdir {
+        /// Server address (overrides config)
+        #[arg(short, long)]
+        server: Option<String>,
+        
+        /// Directory to create, including missing parents
+        path: String,
+    },
+    
+    /// List the versions the server keeps of a file (relative to cwd)
+    Versions {
+        /// Server address (overrides config)
+        #[arg(short, long)]
+        server: Option<String>,
+        
+        /// File on server
+        path: String,
+    },
+    
+    /// Restore a file on the server from one of its versions (relative to cwd)
+    Restore {
+        /// Server address (overrides config)
+        #[arg(short, long)]
+        server: Option<String>,
+        
+        /// File on server
+        path: String,
+        
+        /// Version id as shown by `versions`
+        version: String,
+    },
+    
     /// Get server status
     Status {
         /// Server address (overrides config)
@@ -130,6 +286,21 @@ enum Commands {
         server: Option<String>,
     },
     
+    /// Show, accept or forget pinned server certificates
+    Trust {
+        #[command(subcommand)]
+        action: TrustAction,
+    },
+    
+    /// Hash a pre-shared token for `[[server.tokens]]` (reads stdin if omitted)
+    HashToken {
+        /// Token to hash
+        token: Option<String>,
+    },
+    
+    /// Generate a key for client-side encryption (`[client.encryption]`)
+    Keygen,
+    
     /// Generate default config
     Init {
         /// Config directory
@@ -138,6 +309,30 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum TrustAction {
+    /// Show pinned fingerprints
+    Show {
+        /// Only this server
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+    
+    /// Connect and pin the certificate the server presents now
+    Accept {
+        /// Server address (overrides config)
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+    
+    /// Forget the pinned certificate of a server
+    Forget {
+        /// Server address (overrides config)
+        #[arg(short, long)]
+        server: Option<String>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
@@ -145,7 +340,9 @@ async fn main() -> anyhow::Result<()> {
     // Setup logging
     tracing_subscriber::fmt()
         .with_max_level(if cli.verbose { tracing::Level::DEBUG } else { tracing::Level::INFO })
+        .with_writer(progress::log_writer)
         .init();
+    let _progress = progress::init(cli.progress);
     
     match cli.command {
         Commands::Server { root, bind, audit_log } => {
@@ -153,12 +350,14 @@ async fn main() -> anyhow::Result<()> {
             tracing::info!("Root directory: {:?}", root);
             let log_path = audit_log.unwrap_or_else(|| root.join("audit.jsonl"));
             tracing::info!("Audit log: {:?}", log_path);
-            server::run(&bind, &root, &log_path).await?;
+            let config = config::load_or_default(None)?;
+            server::run(&bind, &root, &log_path, &config).await?;
         }
-        Commands::Put { server, path, dest } => {
+        Commands::Put { server, path, dest, delta, mirror, max_delete, dry_run, checksum, jobs, preserve, compress } => {
             let server = config::resolve_server(server)?;
             tracing::info!("Putting {:?} to {}", path, server);
-            client::put(&server, &path, dest.as_deref()).await?;
+            let opts = client::PutOptions { delta, mirror, max_delete, dry_run, checksum, jobs, preserve, compress };
+            client::put(&server, &path, dest.as_deref(), &opts).await?;
         }
         Commands::List { server, path } => {
             let server = config::resolve_server(server)?;
@@ -226,17 +425,87 @@ async fn main() -> anyhow::Result<()> {
             let _ = state::save(&state);
             client::list(&server, &state.cwd).await?;
         }
+        Commands::Sync { server, local, remote, checksum, dry_run } => {
+            let server = config::resolve_server(server)?;
+            let state = state::load().unwrap_or_default();
+            let remote = match remote {
+                Some(r) => state::join(&state.cwd, &r),
+                None => {
+                    let name = local.canonicalize().ok()
+                        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+                        .ok_or_else(|| anyhow::anyhow!("Cannot derive remote directory from {:?}", local))?;
+                    state::join(&state.cwd, &name)
+                }
+            };
+            tracing::info!("Syncing {:?} with {} on {}", local, remote, server);
+            let opts = sync::SyncOptions { checksum, dry_run };
+            sync::sync(&server, &local, &remote, &opts).await?;
+        }
+        Commands::Rm { server, path, recursive } => {
+            let server = config::resolve_server(server)?;
+            let state = state::load().unwrap_or_default();
+            client::remove(&server, &state::join(&state.cwd, &path), recursive).await?;
+        }
+        Commands::Mv { server, from, to } => {
+            let server = config::resolve_server(server)?;
+            let state = state::load().unwrap_or_default();
+            client::rename(&server, &state::join(&state.cwd, &from), &state::join(&state.cwd, &to)).await?;
+        }
+        Commands::Mkdir { server, path } => {
+            let server = config::resolve_server(server)?;
+            let state = state::load().unwrap_or_default();
+            client::mkdir(&server, &state::join(&state.cwd, &path)).await?;
+        }
+        Commands::Versions { server, path } => {
+            let server = config::resolve_server(server)?;
+            let state = state::load().unwrap_or_default();
+            client::versions(&server, &state::join(&state.cwd, &path)).await?;
+        }
+        Commands::Restore { server, path, version } => {
+            let server = config::resolve_server(server)?;
+            let state = state::load().unwrap_or_default();
+            client::restore(&server, &state::join(&state.cwd, &path), &version).await?;
+        }
         Commands::Status { server } => {
             let server = config::resolve_server(server)?;
             client::status(&server).await?;
         }
-        Commands::View { server, path } => {
+        Commands::View { server, path, offset, length, tail, compress } => {
             let server = config::resolve_server(server)?;
-            client::view(&server, &path).await?;
+            client::view(&server, &path, client::ViewRange { offset, length, tail }, compress).await?;
         }
-        Commands::Get { server, path, dest } => {
+        Commands::Get { server, path, dest, jobs, preserve, compress } => {
             let server = config::resolve_server(server)?;
-            client::get(&server, &path, dest.as_deref()).await?;
+            let opts = client::GetOptions { jobs, preserve, compress };
+            client::get(&server, &path, dest.as_deref(), &opts).await?;
+        }
+        Commands::Trust { action } => match action {
+            TrustAction::Show { server } => client::trust_show(server.as_deref())?,
+            TrustAction::Accept { server } => {
+                let server = config::resolve_server(server)?;
+                client::trust_accept(&server).await?;
+            }
+            TrustAction::Forget { server } => {
+                let server = config::resolve_server(server)?;
+                client::trust_forget(&server)?;
+            }
+        },
+        Commands::HashToken { token } => {
+            let token = match token {
+                Some(t) => t,
+                None => {
+                    let mut line = String::new();
+                    std::io::stdin().read_line(&mut line)?;
+                    line
+                }
+            };
+            println!("{}", auth::hash_token(&token));
+        }
+        Commands::Keygen => {
+            println!("[client.encryption]");
+            println!("key = \"{}\"", crypt::generate_key());
+            println!("# names = true");
+            eprintln!("⚠️  Keep a copy of this key in a safe place: without it the files on the server are lost");
         }
         Commands::Init { config_dir } => {
             config::init(config_dir.as_deref())?;
@@ -0,0 +1,279 @@
+//! Content-addressed object store for deduplication
+//!
+//! In dedup mode every received file is hard-linked to `.objects/<ab>/<hash>`
+//! below the root, or replaced by a link to an existing object with the same
+//! blake3. Visible files and versions are just further links to it, so the
+//! link count is the reference count: an object without other links is garbage.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Name of the store directory in the root (reserved, hidden from clients)
+pub const DIR: &str = ".objects";
+
+/// The object's content really hashes to its name; a name alone is never
+/// trusted before content is handed out under it
+fn intact(object: &Path, hash: &str) -> Result<bool> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(std::fs::File::open(object)?)?;
+    Ok(hasher.finalize().to_hex().eq_ignore_ascii_case(hash))
+}
+
+pub struct ObjectStore {
+    dir: PathBuf,
+    enabled: bool,
+}
+
+impl ObjectStore {
+    /// Store below `root` (canonical)
+    pub fn new(root: &Path, enabled: bool) -> Self {
+        if enabled && cfg!(not(unix)) {
+            tracing::warn!("Deduplication needs hard link counts and is only supported on unix");
+        }
+        Self { dir: root.join(DIR), enabled: enabled && cfg!(unix) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Link the stored object with blake3 `hash` to `dest` (which must not
+    /// exist yet). Returns `false` if there is no such object. Reads the
+    /// whole object to verify it, so call it off the async runtime.
+    pub fn link_to(&self, hash: &str, dest: &Path) -> Result<bool> {
+        // Client-supplied hashes end up in a path, only accept the real thing
+        let valid = hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+        if !self.enabled || !valid {
+            return Ok(false);
+        }
+        let object = self.object_path(hash);
+        if !object.is_file() {
+            return Ok(false);
+        }
+        match intact(&object, hash) {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::warn!("Object {:?} does not match its hash, not using it", object);
+                return Ok(false);
+            }
+            Err(e) => {
+                tracing::warn!("Cannot read object {:?}, not using it: {}", object, e);
+                return Ok(false);
+            }
+        }
+        std::fs::hard_link(&object, dest)?;
+        Ok(true)
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        let hash = hash.to_ascii_lowercase();
+        self.dir.join(&hash[..2]).join(&hash)
+    }
+
+    /// Turn `temp`, whose content hashes to `hash`, into a reference to the
+    /// shared object. Returns `true` if an existing object was reused.
+    pub fn store(&self, temp: &Path, hash: &str) -> Result<bool> {
+        if !self.enabled {
+            return Ok(false);
+        }
+        let object = self.object_path(hash);
+        if object.is_file() {
+            if intact(&object, hash)? {
+                // Link under a fresh name first: if that fails (too many links
+                // already, say), the upload is still there to be used as it is
+                let name = temp.file_name().unwrap_or_default().to_string_lossy();
+                let link = temp.with_file_name(format!(".link{}", name));
+                std::fs::hard_link(&object, &link)?;
+                if let Err(e) = std::fs::rename(&link, temp) {
+                    let _ = std::fs::remove_file(&link);
+                    return Err(e.into());
+                }
+                return Ok(true);
+            }
+            // The fresh upload takes the place of a damaged object
+            tracing::warn!("Object {:?} does not match its hash, replacing it", object);
+            std::fs::remove_file(&object)?;
+        }
+
+        if let Some(parent) = object.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        match std::fs::hard_link(temp, &object) {
+            Ok(()) => Ok(false),
+            // Someone stored the same content meanwhile
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => self.store(temp, hash),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Drop the object behind `path` if `path` is its last reference.
+    /// Call before removing or replacing `path`.
+    pub fn release(&self, path: &Path) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let Ok(metadata) = std::fs::symlink_metadata(path) else {
+                return Ok(());
+            };
+            // Anything else is either not deduplicated or still shared
+            if !metadata.is_file() || metadata.nlink() != 2 {
+                return Ok(());
+            }
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_reader(std::fs::File::open(path)?)?;
+            let object = self.object_path(&hasher.finalize().to_hex());
+            if let Ok(o) = std::fs::symlink_metadata(&object)
+                && o.ino() == metadata.ino()
+                && o.dev() == metadata.dev()
+            {
+                std::fs::remove_file(&object)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Bytes used by everything below `root`, hard links (dedup, versions)
+    /// counted once
+    pub fn physical_size(root: &Path) -> u64 {
+        let mut size = 0u64;
+        let mut seen = std::collections::HashSet::new();
+        for entry in walkdir::WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+            let Ok(metadata) = entry.metadata() else { continue };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            #[cfg(unix)]
+            let inode = {
+                use std::os::unix::fs::MetadataExt;
+                (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+            };
+            #[cfg(not(unix))]
+            let inode: Option<(u64, u64)> = None;
+            if inode.is_some_and(|i| !seen.insert(i)) {
+                continue;
+            }
+            size += metadata.len();
+        }
+        size
+    }
+
+    /// Remove objects nothing links to any more (pruned versions, interrupted
+    /// deletes, dedup switched off). Returns how many were removed.
+    pub fn sweep(&self) -> usize {
+        let mut removed = 0;
+        #[cfg(unix)]
+        for entry in walkdir::WalkDir::new(&self.dir).into_iter().filter_map(|e| e.ok()) {
+            use std::os::unix::fs::MetadataExt;
+
+            let orphan = entry.file_type().is_file() && entry.metadata().is_ok_and(|m| m.nlink() == 1);
+            if !orphan {
+                continue;
+            }
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => removed += 1,
+                Err(e) => tracing::warn!("Could not remove unused object {:?}: {}", entry.path(), e),
+            }
+        }
+        removed
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hank-sync-objects-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// Write `content` to `root/name` and store it like a finished upload
+    fn upload(store: &ObjectStore, root: &Path, name: &str, content: &[u8]) -> bool {
+        let path = root.join(name);
+        std::fs::write(&path, content).unwrap();
+        store.store(&path, &blake3::hash(content).to_hex()).unwrap()
+    }
+
+    fn links(path: &Path) -> u64 {
+        std::fs::metadata(path).unwrap().nlink()
+    }
+
+    #[test]
+    fn counts_references_in_links() {
+        let root = temp_root("refs");
+        let store = ObjectStore::new(&root, true);
+        let object = store.object_path(&blake3::hash(b"same").to_hex());
+
+        assert!(!upload(&store, &root, "a", b"same"));
+        assert_eq!(links(&object), 2);
+        assert!(upload(&store, &root, "b", b"same"));
+        assert_eq!(links(&object), 3);
+        assert_eq!(std::fs::metadata(root.join("b")).unwrap().ino(), std::fs::metadata(&object).unwrap().ino());
+
+        // Still referenced by `b`
+        store.release(&root.join("a")).unwrap();
+        std::fs::remove_file(root.join("a")).unwrap();
+        assert!(object.is_file());
+        // The last reference takes the object with it
+        store.release(&root.join("b")).unwrap();
+        std::fs::remove_file(root.join("b")).unwrap();
+        assert!(!object.exists());
+    }
+
+    #[test]
+    fn keeps_the_upload_if_linking_fails() {
+        let root = temp_root("link-fails");
+        let store = ObjectStore::new(&root, true);
+        upload(&store, &root, "a", b"data");
+
+        // Something in the way of the fresh link
+        let temp = root.join(".b.1.hank-part");
+        std::fs::write(root.join(".link.b.1.hank-part"), "").unwrap();
+        std::fs::write(&temp, b"data").unwrap();
+        assert!(store.store(&temp, &blake3::hash(b"data").to_hex()).is_err());
+        assert_eq!(std::fs::read(&temp).unwrap(), b"data");
+    }
+
+    #[test]
+    fn replaces_damaged_objects() {
+        let root = temp_root("damaged");
+        let store = ObjectStore::new(&root, true);
+        upload(&store, &root, "a", b"good");
+        let hash = blake3::hash(b"good").to_hex();
+        std::fs::write(store.object_path(&hash), b"evil").unwrap();
+
+        assert!(!store.link_to(&hash, &root.join("linked")).unwrap());
+        assert!(!upload(&store, &root, "b", b"good"));
+        assert_eq!(std::fs::read(store.object_path(&hash)).unwrap(), b"good");
+    }
+
+    #[test]
+    fn sweeps_unreferenced_objects() {
+        let root = temp_root("sweep");
+        let store = ObjectStore::new(&root, true);
+        upload(&store, &root, "kept", b"kept");
+        upload(&store, &root, "gone", b"gone");
+        std::fs::remove_file(root.join("gone")).unwrap();
+
+        assert_eq!(store.sweep(), 1);
+        assert!(store.object_path(&blake3::hash(b"kept").to_hex()).is_file());
+        assert!(!store.object_path(&blake3::hash(b"gone").to_hex()).exists());
+    }
+
+    #[test]
+    fn counts_shared_content_once() {
+        let root = temp_root("physical");
+        let store = ObjectStore::new(&root, true);
+        upload(&store, &root, "a", &[1; 1000]);
+        upload(&store, &root, "b", &[1; 1000]);
+        upload(&store, &root, "c", &[2; 10]);
+        assert_eq!(ObjectStore::physical_size(&root), 1010);
+    }
+}
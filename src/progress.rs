@@ -0,0 +1,459 @@
+//! Transfer progress for the client
+//!
+//! Every upload and download reports through a `FileProgress` from `start`.
+//! A background thread renders the totals to stderr: a live bar when stderr
+//! is a terminal, otherwise a JSON line every few seconds plus one per
+//! finished file, so CI logs show where the time went.
+
+use serde::Serialize;
+use std::io::{IsTerminal, Write};
+use std::pin::Pin;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// How progress is shown (`--progress`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// Bar on a terminal, JSON lines otherwise
+    Auto,
+    Bar,
+    Json,
+    Off,
+}
+
+const TICK: Duration = Duration::from_millis(200);
+const JSON_INTERVAL: Duration = Duration::from_secs(5);
+const BAR_WIDTH: usize = 10;
+/// Longer lines would wrap and break redrawing in place
+const LINE_WIDTH: usize = 79;
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+static STATE: Mutex<State> = Mutex::new(State::new());
+
+struct State {
+    started: Option<Instant>,
+    /// Announced by `expect`; otherwise the totals grow with each `start`
+    expected_files: u64,
+    expected_bytes: u64,
+    started_files: u64,
+    started_bytes: u64,
+    files_done: u64,
+    files_failed: u64,
+    /// Bytes of the files that are complete on the receiving side
+    bytes_done: u64,
+    /// Bytes that actually went over the wire
+    bytes_sent: u64,
+    active: Vec<Active>,
+    next_id: u64,
+    /// Smoothed bytes per second
+    rate: f64,
+    sample: Option<(Instant, u64)>,
+    last_json: Option<Instant>,
+    bar_visible: bool,
+    closed: bool,
+}
+
+/// One line of `--progress json` output
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    /// Every few seconds while transfers run
+    Progress {
+        elapsed: f64,
+        files_done: u64,
+        files_failed: u64,
+        files_total: u64,
+        bytes_done: u64,
+        bytes_total: u64,
+        bytes_per_sec: u64,
+        eta_secs: Option<u64>,
+        active: Vec<ActiveFile<'a>>,
+    },
+    /// A transfer ended; `status` is `done`, `skipped` or `failed`
+    File {
+        path: &'a str,
+        size: u64,
+        sent: u64,
+        secs: f64,
+        status: &'a str,
+    },
+    /// The command is finished
+    Done {
+        elapsed: f64,
+        files_done: u64,
+        files_failed: u64,
+        bytes_done: u64,
+        bytes_sent: u64,
+        bytes_per_sec: u64,
+    },
+}
+
+#[derive(Serialize)]
+struct ActiveFile<'a> {
+    path: &'a str,
+    done: u64,
+    size: u64,
+}
+
+struct Active {
+    id: u64,
+    name: String,
+    size: u64,
+    done: u64,
+    sent: u64,
+    since: Instant,
+}
+
+/// Choose how to show progress; the returned guard clears the bar (or
+/// writes the final JSON line) when dropped at the end of `main`
+pub fn init(mode: Mode) -> Finish {
+    let mode = match mode {
+        Mode::Auto if std::io::stderr().is_terminal() => Mode::Bar,
+        Mode::Auto => Mode::Json,
+        other => other,
+    };
+    let _ = MODE.set(mode);
+    Finish
+}
+
+fn mode() -> Mode {
+    MODE.get().copied().unwrap_or(Mode::Off)
+}
+
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Announce `files` transfers of `bytes` in total, for the totals and the ETA
+pub fn expect(files: u64, bytes: u64) {
+    let mut state = state();
+    state.expected_files += files;
+    state.expected_bytes += bytes;
+}
+
+/// Start reporting the transfer of `name` (`size` bytes)
+pub fn start(name: &str, size: u64) -> FileProgress {
+    let mut state = state();
+    let now = Instant::now();
+    if state.started.is_none() {
+        state.started = Some(now);
+        state.last_json = Some(now);
+        if mode() != Mode::Off {
+            std::thread::spawn(render_loop);
+        }
+    }
+    let id = state.next_id;
+    state.next_id += 1;
+    state.started_files += 1;
+    state.started_bytes += size;
+    state.active.push(Active { id, name: name.to_string(), size, done: 0, sent: 0, since: now });
+    FileProgress { id, finished: false }
+}
+
+/// Print something (with `f`) without it running into the bar
+pub fn suspend<T>(f: impl FnOnce() -> T) -> T {
+    let mut state = state();
+    state.clear_bar();
+    f()
+}
+
+/// Handle on one transfer; dropping it without `finish` counts as failed
+pub struct FileProgress {
+    id: u64,
+    finished: bool,
+}
+
+impl FileProgress {
+    /// `n` more bytes went over the wire
+    pub fn advance(&self, n: u64) {
+        let mut state = state();
+        state.bytes_done += n;
+        state.bytes_sent += n;
+        if let Some(active) = state.find(self.id) {
+            active.done += n;
+            active.sent += n;
+        }
+    }
+
+    /// `n` more bytes are in place without being sent (resumed, reused blocks)
+    pub fn skip(&self, n: u64) {
+        let mut state = state();
+        state.bytes_done += n;
+        if let Some(active) = state.find(self.id) {
+            active.done += n;
+        }
+    }
+
+    /// Start over after a failed attempt; nothing counted so far is in place
+    pub fn restart(&self) {
+        let mut state = state();
+        if let Some(active) = state.find(self.id) {
+            let done = std::mem::take(&mut active.done);
+            state.bytes_done -= done;
+        }
+    }
+
+    /// Report `reader`'s bytes as sent while it is read
+    pub fn track<R>(&self, reader: R) -> Tracked<'_, R> {
+        Tracked { inner: reader, progress: self }
+    }
+
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for FileProgress {
+    fn drop(&mut self) {
+        let mut state = state();
+        let Some(index) = state.active.iter().position(|a| a.id == self.id) else {
+            return;
+        };
+        let active = state.active.remove(index);
+        let status = if !self.finished {
+            // Count nothing of a failed file as done
+            state.files_failed += 1;
+            state.bytes_done -= active.done;
+            "failed"
+        } else {
+            state.files_done += 1;
+            state.bytes_done += active.size.saturating_sub(active.done);
+            if active.sent == 0 && active.size > 0 { "skipped" } else { "done" }
+        };
+        if mode() == Mode::Json {
+            print_json(&Event::File {
+                path: &active.name,
+                size: active.size,
+                sent: active.sent,
+                secs: round(active.since.elapsed().as_secs_f64()),
+                status,
+            });
+        }
+    }
+}
+
+/// Reader that reports what passes through it to a `FileProgress`
+pub struct Tracked<'a, R> {
+    inner: R,
+    progress: &'a FileProgress,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Tracked<'_, R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.progress.advance((buf.filled().len() - before) as u64);
+        }
+        poll
+    }
+}
+
+/// See `init`
+pub struct Finish;
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        let mut state = state();
+        state.closed = true;
+        let Some(started) = state.started else {
+            return;
+        };
+        match mode() {
+            Mode::Bar => state.clear_bar(),
+            Mode::Json => {
+                let secs = started.elapsed().as_secs_f64();
+                print_json(&Event::Done {
+                    elapsed: round(secs),
+                    files_done: state.files_done,
+                    files_failed: state.files_failed,
+                    bytes_done: state.bytes_done,
+                    bytes_sent: state.bytes_sent,
+                    bytes_per_sec: (state.bytes_sent as f64 / secs.max(0.001)) as u64,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writer for log lines: takes the bar out of the way first
+pub struct LogWriter;
+
+pub fn log_writer() -> LogWriter {
+    LogWriter
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = state();
+        state.clear_bar();
+        std::io::stdout().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()
+    }
+}
+
+fn render_loop() {
+    loop {
+        std::thread::sleep(TICK);
+        let mut state = state();
+        if state.closed {
+            return;
+        }
+        state.update_rate();
+        match mode() {
+            Mode::Bar => state.draw_bar(),
+            Mode::Json if state.last_json.is_none_or(|t| t.elapsed() >= JSON_INTERVAL) => {
+                state.last_json = Some(Instant::now());
+                print_json(&state.progress_event());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            started: None,
+            expected_files: 0,
+            expected_bytes: 0,
+            started_files: 0,
+            started_bytes: 0,
+            files_done: 0,
+            files_failed: 0,
+            bytes_done: 0,
+            bytes_sent: 0,
+            active: Vec::new(),
+            next_id: 0,
+            rate: 0.0,
+            sample: None,
+            last_json: None,
+            bar_visible: false,
+            closed: false,
+        }
+    }
+
+    fn find(&mut self, id: u64) -> Option<&mut Active> {
+        self.active.iter_mut().find(|a| a.id == id)
+    }
+
+    fn total_files(&self) -> u64 {
+        self.expected_files.max(self.started_files)
+    }
+
+    fn total_bytes(&self) -> u64 {
+        self.expected_bytes.max(self.started_bytes)
+    }
+
+    fn update_rate(&mut self) {
+        let now = Instant::now();
+        if let Some((at, sent)) = self.sample {
+            let secs = now.duration_since(at).as_secs_f64();
+            let current = (self.bytes_sent - sent) as f64 / secs.max(0.001);
+            self.rate = if self.rate == 0.0 { current } else { 0.8 * self.rate + 0.2 * current };
+        }
+        self.sample = Some((now, self.bytes_sent));
+    }
+
+    fn eta(&self) -> Option<u64> {
+        let remaining = self.total_bytes().saturating_sub(self.bytes_done);
+        (self.rate >= 1.0).then(|| (remaining as f64 / self.rate) as u64)
+    }
+
+    fn draw_bar(&mut self) {
+        let total = self.total_bytes();
+        let done = self.bytes_done.min(total);
+        let fraction = if total == 0 { 1.0 } else { done as f64 / total as f64 };
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let mut line = format!(
+            "[{}{}] {:>3}% {}/{} {}/{} files {}/s ETA {}",
+            "#".repeat(filled),
+            ".".repeat(BAR_WIDTH - filled),
+            (fraction * 100.0) as u32,
+            human(done),
+            human(total),
+            self.files_done,
+            self.total_files(),
+            human(self.rate as u64),
+            self.eta().map_or("-".to_string(), duration),
+        );
+        if let Some(current) = self.active.first() {
+            let name = current.name.rsplit('/').next().unwrap_or(&current.name);
+            let percent = (current.done * 100).checked_div(current.size).unwrap_or(100);
+            line.push_str(&format!(" {} {}%", name, percent.min(100)));
+            if self.active.len() > 1 {
+                line.push_str(&format!(" +{}", self.active.len() - 1));
+            }
+        }
+        let line: String = line.chars().take(LINE_WIDTH).collect();
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[K{}", line);
+        let _ = stderr.flush();
+        self.bar_visible = true;
+    }
+
+    fn clear_bar(&mut self) {
+        if self.bar_visible {
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[K");
+            let _ = stderr.flush();
+            self.bar_visible = false;
+        }
+    }
+
+    fn progress_event(&self) -> Event<'_> {
+        Event::Progress {
+            elapsed: round(self.started.map_or(0.0, |s| s.elapsed().as_secs_f64())),
+            files_done: self.files_done,
+            files_failed: self.files_failed,
+            files_total: self.total_files(),
+            bytes_done: self.bytes_done,
+            bytes_total: self.total_bytes(),
+            bytes_per_sec: self.rate as u64,
+            eta_secs: self.eta(),
+            active: self.active.iter()
+                .map(|a| ActiveFile { path: &a.name, done: a.done, size: a.size })
+                .collect(),
+        }
+    }
+}
+
+/// Callers hold the state lock, so lines never interleave
+fn print_json(event: &Event) {
+    if let Ok(line) = serde_json::to_string(event) {
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+}
+
+fn round(secs: f64) -> f64 {
+    (secs * 100.0).round() / 100.0
+}
+
+/// `1.5 MB`
+fn human(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// `1:05` or `2:01:05`
+fn duration(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 { format!("{}:{:02}:{:02}", h, m, s) } else { format!("{}:{:02}", m, s) }
+}
@@ -1,8 +1,10 @@
 //! Protocol messages
 
 use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "cmd", rename_all = "snake_case")]
 pub enum Request {
     Put {
@@ -10,6 +12,34 @@ pub enum Request {
         size: u64,
         #[serde(skip_serializing_if = "Option::is_none")]
         hash: Option<String>,
+        /// Resume a partial upload: only bytes from `offset` on follow
+        #[serde(default)]
+        offset: u64,
+        /// Attributes to apply (`--preserve`); a symlink carries no data
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        meta: Option<FileMeta>,
+        /// The data that follows is compressed (up to the end of the stream)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<Compression>,
+    },
+    /// Ask how many bytes of a partial upload the server already holds
+    Resume {
+        path: String,
+        size: u64,
+        hash: String,
+    },
+    /// Ask for block signatures of an existing file (for delta uploads)
+    Signature {
+        path: String,
+    },
+    /// Rebuild `path` from its current content plus a stream of delta ops
+    Delta {
+        path: String,
+        size: u64,
+        hash: String,
+        block_size: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        meta: Option<FileMeta>,
     },
     List {
         path: String,
@@ -20,8 +50,94 @@ pub enum Request {
     },
     Get {
         path: String,
+        /// First byte to send
+        #[serde(default)]
+        offset: u64,
+        /// Maximum number of bytes to send (default: up to end of file)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        length: Option<u64>,
+        /// Include attributes in the response and report symlinks as such
+        #[serde(default)]
+        meta: bool,
+        /// Compression the client accepts; the server may still send raw
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<Compression>,
+    },
+    /// All files below `path` with size, mtime and optionally blake3
+    Manifest {
+        path: String,
+        #[serde(default)]
+        hash: bool,
+    },
+    /// Remove a file or an empty directory (any directory if `recursive`)
+    Delete {
+        path: String,
+        #[serde(default)]
+        recursive: bool,
+    },
+    /// Move a file or directory within the root
+    Rename {
+        from: String,
+        to: String,
+    },
+    /// Create a directory (and missing parents)
+    Mkdir {
+        path: String,
+    },
+    /// Earlier versions of a file kept by the server
+    Versions {
+        path: String,
+    },
+    /// Replace a file with one of its versions (the current copy becomes a version)
+    Restore {
+        path: String,
+        version: String,
     },
     Status,
+    /// Present a pre-shared token; must be the first request of a connection
+    Auth {
+        token: String,
+    },
+}
+
+impl Request {
+    /// Server paths this request touches
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            Request::Put { path, .. }
+            | Request::Resume { path, .. }
+            | Request::Signature { path }
+            | Request::Delta { path, .. }
+            | Request::List { path, .. }
+            | Request::Get { path, .. }
+            | Request::Manifest { path, .. }
+            | Request::Delete { path, .. }
+            | Request::Mkdir { path }
+            | Request::Versions { path }
+            | Request::Restore { path, .. } => vec![path],
+            Request::Rename { from, to } => vec![from, to],
+            Request::Status | Request::Auth { .. } => Vec::new(),
+        }
+    }
+
+    /// Like `paths`, for rewriting them
+    pub fn paths_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Request::Put { path, .. }
+            | Request::Resume { path, .. }
+            | Request::Signature { path }
+            | Request::Delta { path, .. }
+            | Request::List { path, .. }
+            | Request::Get { path, .. }
+            | Request::Manifest { path, .. }
+            | Request::Delete { path, .. }
+            | Request::Mkdir { path }
+            | Request::Versions { path }
+            | Request::Restore { path, .. } => vec![path],
+            Request::Rename { from, to } => vec![from, to],
+            Request::Status | Request::Auth { .. } => Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,13 +150,54 @@ pub enum Response {
     List {
         entries: Vec<FileEntry>,
     },
+    /// `size` bytes starting at `offset` follow; `total` is the full file size
     File {
         size: u64,
+        #[serde(default)]
+        offset: u64,
+        #[serde(default)]
+        total: u64,
+        /// Last modification of the file (seconds since the epoch), tells
+        /// whether an interrupted download may be continued
+        #[serde(default)]
+        modified: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        meta: Option<FileMeta>,
+        /// The data that follows is compressed (up to the end of the stream)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<Compression>,
+    },
+    /// Answer to `Get` on a directory; fetch its files individually
+    Directory,
+    /// Answer to `Put`/`Delta`: the server already holds this content at the
+    /// destination (or linked it there), no bytes need to be sent
+    Identical,
+    Offset {
+        offset: u64,
+    },
+    /// Block signatures; `blocks` is empty if the file does not exist
+    Signature {
+        size: u64,
+        block_size: u32,
+        blocks: Vec<BlockSignature>,
+    },
+    /// Files below the requested directory; empty if it does not exist
+    Manifest {
+        entries: Vec<ManifestEntry>,
+    },
+    /// Versions of a file, newest first
+    Versions {
+        versions: Vec<VersionEntry>,
     },
     Status {
         root: String,
+        /// Size of the visible files
         total_size: u64,
         file_count: u64,
+        /// Disk space actually used, counting shared content once
+        /// (includes versions)
+        #[serde(default)]
+        physical_size: u64,
     },
     Error {
         message: String,
@@ -55,3 +212,99 @@ pub struct FileEntry {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub modified: Option<u64>,
 }
+
+/// File attributes carried with `--preserve`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FileMeta {
+    /// Seconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+    /// Unix permission bits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Link target (relative); the entry is a symlink, not a file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlink: Option<String>,
+}
+
+impl FileMeta {
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let mode = Some(std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()));
+        #[cfg(not(unix))]
+        let mode = None;
+
+        Self {
+            mtime: metadata.modified().ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            mode,
+            symlink: None,
+        }
+    }
+
+    /// What of this the server puts on a file it received: its owner (the
+    /// server) keeps read and write access, and a `shared` file (one inode
+    /// for all copies with dedup) keeps the server's own permissions
+    pub fn on_server(&self, shared: bool) -> Self {
+        Self {
+            mode: self.mode.filter(|_| !shared).map(|mode| mode | 0o600),
+            ..self.clone()
+        }
+    }
+
+    /// Set mtime and permissions on a regular file
+    pub fn apply(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(mtime) = self.mtime {
+            std::fs::File::options().write(true).open(path)?
+                .set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+        }
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            // Never hand out setuid/setgid/sticky bits from the other side
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    Zstd,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Compression {
+    pub codec: Codec,
+    pub level: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Relative to the requested directory, `/`-separated
+    pub path: String,
+    pub size: u64,
+    /// Seconds since the epoch
+    pub modified: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionEntry {
+    /// UTC timestamp of when the version was taken, e.g. `20240101T120000.000000Z`
+    pub id: String,
+    pub size: u64,
+    /// mtime of the file when it was replaced, seconds since the epoch
+    pub modified: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockSignature {
+    /// Rolling (rsync-style) checksum
+    pub weak: u32,
+    /// Truncated blake3, hex
+    pub strong: String,
+}
@@ -0,0 +1,303 @@
+//! Resolve client-supplied paths inside the server root
+//!
+//! Protocol paths are always relative to the server root; a leading `/`
+//! denotes the root itself. Anything that could leave the root (`..`,
+//! drive prefixes, symlinks pointing outside) is rejected, never rewritten.
+//! Top-level directories the server keeps for itself are off limits too.
+
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PathError {
+    #[error("Path must not contain '..': {0}")]
+    ParentDir(String),
+    #[error("Path must be relative to the server root: {0}")]
+    Absolute(String),
+    #[error("Path leaves the server root via a symlink: {0}")]
+    Escapes(String),
+    #[error("Symlink target must be relative and stay inside the root: {0}")]
+    LinkTarget(String),
+    #[error("Path is reserved for the server: {0}")]
+    Reserved(String),
+}
+
+/// Server-internal directories directly below the root
+const RESERVED: &[&str] = &[crate::versions::DIR, crate::objects::DIR];
+
+/// The server root and what inside it clients may not touch
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    root: PathBuf,
+    /// Further files the server writes inside the root (the audit log),
+    /// root-relative
+    protected: Vec<PathBuf>,
+}
+
+/// A path that was checked to stay inside the root
+#[derive(Debug, Clone)]
+pub struct SafePath {
+    /// Absolute path on the server
+    pub full: PathBuf,
+    /// Normalized path relative to the root (`/`-separated, empty for the
+    /// root), with symlinked directories along the way resolved
+    pub rel: String,
+    /// Where `rel` leads if the entry itself is a symlink, else the same
+    pub real: String,
+}
+
+/// Parse a protocol path into its normal components
+pub fn relative(path: &str) -> Result<PathBuf, PathError> {
+    let mut rel = PathBuf::new();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => rel.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => return Err(PathError::ParentDir(path.to_string())),
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(PathError::Absolute(path.to_string()));
+            }
+        }
+    }
+    Ok(rel)
+}
+
+impl Sandbox {
+    /// Sandbox for `root` (which must be canonical); those of `files` that
+    /// lie inside it are reserved
+    pub fn new(root: &Path, files: &[&Path]) -> Self {
+        let protected = files.iter()
+            .filter_map(|file| {
+                // A bare file name has an empty parent: the current directory
+                let parent = match file.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => std::path::absolute(parent).ok()?,
+                    _ => std::env::current_dir().ok()?,
+                };
+                let parent = parent.canonicalize().unwrap_or(parent);
+                Some(parent.join(file.file_name()?).strip_prefix(root).ok()?.to_path_buf())
+            })
+            .collect();
+        Self { root: root.to_path_buf(), protected }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolve `path` below the root.
+    ///
+    /// The deepest existing ancestor is canonicalized so symlinks anywhere
+    /// along the way are followed and checked against the root. The result
+    /// names the real location, so access checks cannot be sidestepped
+    /// through a symlink.
+    pub fn resolve(&self, path: &str) -> Result<SafePath, PathError> {
+        let root = self.root.as_path();
+        let rel = relative(path)?;
+        if self.in_reserved(&rel) {
+            return Err(PathError::Reserved(path.to_string()));
+        }
+        let full = root.join(&rel);
+
+        let mut existing = full.as_path();
+        while std::fs::symlink_metadata(existing).is_err() {
+            match existing.parent() {
+                Some(parent) if parent.starts_with(root) => existing = parent,
+                _ => break,
+            }
+        }
+
+        // Dangling symlinks fail to canonicalize and are treated as escapes
+        let escapes = || PathError::Escapes(path.to_string());
+        let canonical = existing.canonicalize().map_err(|_| escapes())?;
+        let missing = full.strip_prefix(existing).map_err(|_| escapes())?;
+        // Joining an empty remainder would append a `/` and make files look like directories
+        let real = if missing.as_os_str().is_empty() { canonical } else { canonical.join(missing) };
+
+        // Operations on a symlink itself (replace, delete, move) act on the
+        // link in its real parent directory, not on what it points to
+        let is_link = std::fs::symlink_metadata(&full).is_ok_and(|m| m.file_type().is_symlink());
+        let entry = match (is_link, full.parent(), full.file_name()) {
+            (true, Some(parent), Some(name)) => parent.canonicalize().map_err(|_| escapes())?.join(name),
+            _ => real.clone(),
+        };
+
+        let rel = root_relative(root, &entry).ok_or_else(escapes)?;
+        let real = root_relative(root, &real).ok_or_else(escapes)?;
+        // A symlink must not lead into the server's own directories either
+        if self.in_reserved(Path::new(&rel)) || self.in_reserved(Path::new(&real)) {
+            return Err(PathError::Reserved(path.to_string()));
+        }
+        Ok(SafePath { full: entry, rel, real })
+    }
+
+    /// `path` (absolute) is (inside) one of the reserved directories or files
+    pub fn is_reserved(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.root).is_ok_and(|rel| self.in_reserved(rel))
+    }
+
+    fn in_reserved(&self, rel: &Path) -> bool {
+        rel.components().next().is_some_and(|c| RESERVED.iter().any(|r| c.as_os_str() == *r))
+            || self.protected.iter().any(|f| rel.starts_with(f))
+    }
+
+    /// Removing or moving root-relative `rel` would take a reserved file with it
+    pub fn holds_reserved(&self, rel: &str) -> bool {
+        self.protected.iter().any(|f| f.starts_with(rel))
+    }
+}
+
+/// `/`-separated path of `path` below `root`, `None` if it is outside
+fn root_relative(root: &Path, path: &Path) -> Option<String> {
+    let rel = path.strip_prefix(root).ok()?;
+    Some(rel.to_string_lossy().replace('\\', "/"))
+}
+
+/// Check that a symlink at root-relative `link` pointing to `target` stays
+/// inside the root. Only relative targets are accepted. Returns the target
+/// relative to the root (before following any further symlinks).
+pub fn check_link_target(link: &str, target: &str) -> Result<String, PathError> {
+    let mut resolved = relative(link)?;
+    resolved.pop();
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir if resolved.pop() => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(PathError::LinkTarget(format!("{} -> {}", link, target)));
+            }
+        }
+    }
+    Ok(resolved.to_string_lossy().replace('\\', "/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hank-sync-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn resolve(root: &Path, path: &str) -> Result<SafePath, PathError> {
+        Sandbox::new(root, &[]).resolve(path)
+    }
+
+    fn is_reserved(root: &Path, path: &Path) -> bool {
+        Sandbox::new(root, &[]).is_reserved(path)
+    }
+
+    #[test]
+    fn rejects_parent_components() {
+        let root = temp_root("parent");
+        for path in ["..", "../etc/passwd", "a/../../b", "/a/b/../../..", "./.."] {
+            assert!(matches!(resolve(&root, path), Err(PathError::ParentDir(_))), "{}", path);
+        }
+    }
+
+    #[test]
+    fn keeps_legitimate_names() {
+        let root = temp_root("names");
+        let resolved = resolve(&root, "/dir/a..b.txt").unwrap();
+        assert_eq!(resolved.rel, "dir/a..b.txt");
+        assert_eq!(resolved.full, root.join("dir").join("a..b.txt"));
+
+        let resolved = resolve(&root, "//x/./y/").unwrap();
+        assert_eq!(resolved.rel, "x/y");
+
+        let resolved = resolve(&root, "/").unwrap();
+        assert_eq!(resolved.rel, "");
+        assert_eq!(resolved.full, root);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_out_of_root() {
+        let root = temp_root("symlink");
+        let outside = temp_root("symlink-outside");
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(root.join("missing"), root.join("dangling")).unwrap();
+        std::fs::create_dir(root.join("inside")).unwrap();
+        std::os::unix::fs::symlink(root.join("inside"), root.join("alias")).unwrap();
+
+        assert!(matches!(resolve(&root, "escape"), Err(PathError::Escapes(_))));
+        assert!(matches!(resolve(&root, "escape/new/file.txt"), Err(PathError::Escapes(_))));
+        assert!(matches!(resolve(&root, "dangling"), Err(PathError::Escapes(_))));
+        assert!(resolve(&root, "alias/file.txt").is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_the_real_location() {
+        let root = temp_root("real");
+        std::fs::create_dir_all(root.join("private")).unwrap();
+        std::fs::write(root.join("private/secret"), "x").unwrap();
+        std::fs::create_dir_all(root.join("artifacts/x")).unwrap();
+        std::os::unix::fs::symlink("../../private", root.join("artifacts/x/s")).unwrap();
+        std::os::unix::fs::symlink("../../private/secret", root.join("artifacts/x/f")).unwrap();
+
+        let through = resolve(&root, "artifacts/x/s/secret").unwrap();
+        assert_eq!((through.rel.as_str(), through.real.as_str()), ("private/secret", "private/secret"));
+        assert_eq!(through.full.as_os_str(), root.join("private/secret").as_os_str());
+        let plain = resolve(&root, "private/secret").unwrap();
+        assert_eq!(plain.full.as_os_str(), root.join("private/secret").as_os_str());
+        let new = resolve(&root, "artifacts/x/s/new/file").unwrap();
+        assert_eq!(new.rel, "private/new/file");
+
+        // The link itself stays where it is, but its target is known
+        let link = resolve(&root, "artifacts/x/f").unwrap();
+        assert_eq!((link.rel.as_str(), link.real.as_str()), ("artifacts/x/f", "private/secret"));
+        assert_eq!(link.full, root.join("artifacts/x/f"));
+    }
+
+    #[test]
+    fn rejects_reserved_dirs() {
+        let root = temp_root("reserved");
+        for path in ["/.versions", ".versions/a.txt", "./.versions/x", ".objects/ab"] {
+            assert!(matches!(resolve(&root, path), Err(PathError::Reserved(_))), "{}", path);
+        }
+        assert!(resolve(&root, "a/.versions").is_ok());
+
+        #[cfg(unix)]
+        {
+            std::fs::create_dir_all(root.join(".objects/ab")).unwrap();
+            std::os::unix::fs::symlink(".objects", root.join("o")).unwrap();
+            std::os::unix::fs::symlink(".objects/ab", root.join("p")).unwrap();
+            for path in ["o/ab/x", "o", "p", "p/x"] {
+                assert!(matches!(resolve(&root, path), Err(PathError::Reserved(_))), "{}", path);
+            }
+        }
+        assert!(is_reserved(&root, &root.join(".versions/a")));
+        assert!(!is_reserved(&root, &root.join("a/.versions")));
+    }
+
+    #[test]
+    fn protects_server_files() {
+        let root = temp_root("protected");
+        let sandbox = Sandbox::new(&root, &[&root.join("logs/audit.jsonl"), Path::new("/elsewhere/audit.jsonl")]);
+        assert!(matches!(sandbox.resolve("logs/audit.jsonl"), Err(PathError::Reserved(_))));
+        assert!(sandbox.resolve("logs/other.jsonl").is_ok());
+        assert!(sandbox.holds_reserved("logs"));
+        assert!(!sandbox.holds_reserved("log"));
+        assert!(sandbox.is_reserved(&root.join("logs/audit.jsonl")));
+        // Other sandboxes are not affected
+        assert!(resolve(&root, "logs/audit.jsonl").is_ok());
+
+        // A bare file name lies in the current directory
+        let cwd = std::env::current_dir().unwrap().canonicalize().unwrap();
+        let sandbox = Sandbox::new(&cwd, &[Path::new("audit.jsonl")]);
+        assert!(matches!(sandbox.resolve("audit.jsonl"), Err(PathError::Reserved(_))));
+    }
+
+    #[test]
+    fn checks_link_targets() {
+        assert_eq!(check_link_target("a/b/link", "../c/file").unwrap(), "a/c/file");
+        assert_eq!(check_link_target("a/link", "./b/../c").unwrap(), "a/c");
+        for (link, target) in [("a/link", "../../etc/passwd"), ("link", "b/../../x"), ("link", "/etc/passwd")] {
+            assert!(matches!(check_link_target(link, target), Err(PathError::LinkTarget(_))), "{}", target);
+        }
+    }
+}
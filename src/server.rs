@@ -3,40 +3,104 @@
 use anyhow::Result;
 use quinn::Endpoint;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::mpsc;
 
+use crate::acl::{self, AclRule};
 use crate::audit::{AuditEntry, AuditEvent, AuditLogger};
-use crate::protocol::{Request, Response};
+use crate::auth::{self, RateLimiter, TokenEntry};
+use crate::compress;
+use crate::config::{self, Config};
+use crate::delta;
+use crate::known_servers;
+use crate::objects::ObjectStore;
+use crate::protocol::{Compression, FileMeta, ManifestEntry, Request, Response};
+use crate::sandbox::{self, SafePath, Sandbox};
 use crate::tls;
+use crate::versions::VersionStore;
 
-pub async fn run(bind: &str, root: &Path, audit_log: &Path) -> Result<()> {
-    // Ensure root directory exists
+/// Suffix of in-progress upload files (hidden siblings of their destination)
+const PART_SUFFIX: &str = ".hank-part";
+
+/// Largest request accepted; requests are read before authentication
+const MAX_REQUEST: usize = 1024 * 1024;
+
+/// Partial uploads untouched for this long are removed at server start
+const PARTIAL_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub async fn run(bind: &str, root: &Path, audit_log: &Path, config: &Config) -> Result<()> {
+    // Ensure root directory exists; sandbox checks compare against its canonical form
     fs::create_dir_all(root).await?;
+    let root = &fs::canonicalize(root).await?;
+    
+    // Drop leftovers from transfers that were never resumed
+    let stale = cleanup_partials(root).await;
+    if stale > 0 {
+        tracing::info!("🧹 Removed {} stale partial upload(s)", stale);
+    }
     
     // Setup audit logger
     let logger = AuditLogger::new(audit_log.to_path_buf()).await?;
     logger.log(AuditEntry::new(AuditEvent::ServerStart)
         .with_message(format!("bind={} root={}", bind, root.display()))).await;
     
-    // Setup TLS
-    let (cert, key) = tls::generate_self_signed()?;
-    let server_config = tls::server_config(cert, key)?;
+    // Setup TLS with a stable identity
+    let addr: SocketAddr = bind.parse()?;
+    let mut names = config.server.names.clone();
+    if !addr.ip().is_unspecified() && !names.contains(&addr.ip().to_string()) {
+        names.push(addr.ip().to_string());
+    }
+    let (cert, key) = tls::server_identity(&config.tls, &config::config_dir()?, &names)?;
+    tracing::info!("🔐 Certificate fingerprint: {}", known_servers::fingerprint(&cert[0]));
+    let server_config = tls::server_config(cert, key, config.tls.client_ca.as_deref())?;
+    if let Some(ca) = &config.tls.client_ca {
+        tracing::info!("🔐 Requiring client certificates signed by {:?}", ca);
+    }
     
     // Bind endpoint
-    let endpoint = Endpoint::server(server_config, bind.parse()?)?;
+    let endpoint = Endpoint::server(server_config, addr)?;
     tracing::info!("🚀 Server listening on {}", bind);
     tracing::info!("📁 Root: {:?}", root);
     tracing::info!("📋 Audit log: {:?}", audit_log);
     
+    if !config.server.acl.is_empty() {
+        tracing::info!("🛡️  {} ACL rule(s) active", config.server.acl.len());
+    }
+    if !config.server.tokens.is_empty() {
+        tracing::info!("🔑 Token authentication required ({} token(s))", config.server.tokens.len());
+    }
+    if let Some(retention) = &config.server.versioning {
+        tracing::info!("🕘 Versioning on (keep last {}, daily for {} days)", retention.keep_last, retention.keep_daily);
+    }
+    let storage = Storage {
+        versions: VersionStore::new(root, config.server.versioning.clone()),
+        objects: ObjectStore::new(root, config.server.dedup),
+    };
+    if storage.objects.is_enabled() {
+        tracing::info!("🧬 Deduplicating storage on");
+    }
+    let unused = storage.objects.sweep();
+    if unused > 0 {
+        tracing::info!("🧹 Removed {} unused object(s)", unused);
+    }
+    let shared = Arc::new(Shared {
+        sandbox: Sandbox::new(root, &[audit_log]),
+        acl: config.server.acl.clone(),
+        tokens: config.server.tokens.clone(),
+        auth_failures: RateLimiter::default(),
+        storage,
+        audit_tx: logger.sender(),
+    });
+    
     // Accept connections
     while let Some(incoming) = endpoint.accept().await {
-        let root = root.to_path_buf();
-        let audit_tx = logger.sender();
+        let shared = shared.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(incoming, &root, audit_tx).await {
+            if let Err(e) = handle_connection(incoming, shared).await {
                 tracing::error!("Connection error: {}", e);
             }
         });
@@ -45,18 +109,96 @@ pub async fn run(bind: &str, root: &Path, audit_log: &Path) -> Result<()> {
     Ok(())
 }
 
-async fn handle_connection(
-    incoming: quinn::Incoming,
-    root: &Path,
+/// State shared by all connections
+struct Shared {
+    sandbox: Sandbox,
+    acl: Vec<AclRule>,
+    tokens: Vec<TokenEntry>,
+    auth_failures: RateLimiter,
+    storage: Storage,
     audit_tx: mpsc::Sender<AuditEntry>,
-) -> Result<()> {
-    let connection = incoming.await?;
-    let remote = connection.remote_address();
-    tracing::info!("📥 Connection from {}", remote);
+}
+
+/// Where replaced content goes and how it is stored
+struct Storage {
+    versions: VersionStore,
+    objects: ObjectStore,
+}
+
+impl Storage {
+    /// `full` (root-relative `rel`) is about to be replaced or removed: keep
+    /// a version of it and drop its object if nothing else refers to it
+    fn retire(&self, rel: &str, full: &Path) -> Result<Option<String>> {
+        let version = self.versions.archive(rel, full)?;
+        self.objects.release(full)?;
+        Ok(version)
+    }
+}
+
+/// Who is on the other end of a connection
+#[derive(Debug, Clone)]
+struct Peer {
+    remote: SocketAddr,
+    /// From a verified client certificate (mTLS)
+    identity: Option<tls::ClientIdentity>,
+    /// Name of the pre-shared token the client authenticated with
+    token: Option<String>,
+}
+
+impl Peer {
+    /// Audit entry pre-filled with remote address and client identity
+    fn audit(&self, event: AuditEvent) -> AuditEntry {
+        let entry = AuditEntry::new(event).with_remote(self.remote);
+        match (&self.identity, &self.token) {
+            (Some(id), _) => entry.with_client(&id.name),
+            (None, Some(token)) => entry.with_client(format!("token:{}", token)),
+            (None, None) => entry,
+        }
+    }
+    
+    fn subject(&self) -> acl::Subject<'_> {
+        acl::Subject {
+            ip: self.remote.ip(),
+            name: self.identity.as_ref().map(|id| id.name.as_str()),
+            fingerprint: self.identity.as_ref().map(|id| id.fingerprint.as_str()),
+            token: self.token.as_deref(),
+        }
+    }
+}
+
+async fn handle_connection(incoming: quinn::Incoming, shared: Arc<Shared>) -> Result<()> {
+    let audit_tx = &shared.audit_tx;
+    let remote = incoming.remote_address();
+    let connection = match incoming.await {
+        Ok(c) => c,
+        Err(e) => {
+            // e.g. missing or untrusted client certificate
+            let _ = audit_tx.send(AuditEntry::new(AuditEvent::Error)
+                .with_remote(remote)
+                .with_success(false)
+                .with_message(format!("Handshake failed: {}", e))).await;
+            return Err(e.into());
+        }
+    };
+    let mut peer = Peer { remote, identity: tls::client_identity(&connection), token: None };
+    match &peer.identity {
+        Some(id) => tracing::info!("📥 Connection from {} ({}, {})", remote, id.name, id.fingerprint),
+        None => tracing::info!("📥 Connection from {}", remote),
+    }
     
     // Log connection
-    let _ = audit_tx.send(AuditEntry::new(AuditEvent::Connect)
-        .with_remote(remote)).await;
+    let _ = audit_tx.send(peer.audit(AuditEvent::Connect)).await;
+    
+    // Without a client certificate, a valid token must come first
+    if !shared.tokens.is_empty() && peer.identity.is_none() {
+        match authenticate(&connection, &shared, &peer).await? {
+            Some(name) => peer.token = Some(name),
+            None => {
+                connection.close(1u32.into(), b"unauthorized");
+                return Ok(());
+            }
+        }
+    }
     
     loop {
         // Accept bidirectional stream
@@ -64,14 +206,12 @@ async fn handle_connection(
             Ok(s) => s,
             Err(quinn::ConnectionError::ApplicationClosed(_)) => {
                 tracing::info!("Connection closed by peer");
-                let _ = audit_tx.send(AuditEntry::new(AuditEvent::Disconnect)
-                    .with_remote(remote)).await;
+                let _ = audit_tx.send(peer.audit(AuditEvent::Disconnect)).await;
                 break;
             }
             Err(e) => {
                 tracing::error!("Stream error: {}", e);
-                let _ = audit_tx.send(AuditEntry::new(AuditEvent::Error)
-                    .with_remote(remote)
+                let _ = audit_tx.send(peer.audit(AuditEvent::Error)
                     .with_success(false)
                     .with_message(e.to_string())).await;
                 break;
@@ -79,11 +219,11 @@ async fn handle_connection(
         };
         
         let (send, recv) = stream;
-        let root = root.to_path_buf();
-        let tx = audit_tx.clone();
+        let shared = shared.clone();
+        let peer = peer.clone();
         
         tokio::spawn(async move {
-            if let Err(e) = handle_stream(send, recv, &root, remote, tx).await {
+            if let Err(e) = handle_stream(send, recv, &shared, &peer).await {
                 tracing::error!("Stream error: {}", e);
             }
         });
@@ -92,126 +232,774 @@ async fn handle_connection(
     Ok(())
 }
 
-async fn handle_stream(
-    mut send: quinn::SendStream,
-    mut recv: quinn::RecvStream,
-    root: &Path,
-    remote: SocketAddr,
-    audit_tx: mpsc::Sender<AuditEntry>,
-) -> Result<()> {
-    // Read request header (length-prefixed JSON)
+/// Handle the `Auth` request on the first stream; `None` if the client was refused
+async fn authenticate(connection: &quinn::Connection, shared: &Shared, peer: &Peer) -> Result<Option<String>> {
+    let (mut send, mut recv) = connection.accept_bi().await?;
+    let request = read_request(&mut recv).await?;
+    let ip = peer.remote.ip();
+    
+    let reason = match request {
+        _ if shared.auth_failures.is_blocked(ip) => {
+            "Too many failed authentication attempts, try again later".to_string()
+        }
+        Request::Auth { token } => match auth::verify(&shared.tokens, &token) {
+            Some(name) => {
+                shared.auth_failures.clear(ip);
+                tracing::info!("🔑 {} authenticated with token '{}'", peer.remote, name);
+                let _ = shared.audit_tx.send(peer.audit(AuditEvent::Authenticated)
+                    .with_client(format!("token:{}", name))).await;
+                send_response(&mut send, Response::Ok).await?;
+                return Ok(Some(name.to_string()));
+            }
+            None => {
+                shared.auth_failures.record_failure(ip);
+                "Invalid token".to_string()
+            }
+        },
+        _ => {
+            shared.auth_failures.record_failure(ip);
+            "Authentication required".to_string()
+        }
+    };
+    
+    tracing::warn!("⛔ {}: {}", peer.remote, reason);
+    let _ = shared.audit_tx.send(peer.audit(AuditEvent::AuthFailed)
+        .with_success(false)
+        .with_message(reason.clone())).await;
+    send_response(&mut send, Response::Error { message: reason }).await?;
+    // Give the client a moment to read the error before the connection goes away
+    let _ = tokio::time::timeout(Duration::from_secs(5), send.stopped()).await;
+    Ok(None)
+}
+
+/// Why `peer` may not create a symlink at `link` to `target`: it must not
+/// lead anywhere the client could not write to itself
+fn link_denied(shared: &Shared, peer: &Peer, link: &SafePath, target: &str) -> Option<String> {
+    // Targets leaving the root are refused with a clearer message later on
+    let target = sandbox::check_link_target(&link.rel, target).ok()?;
+    match shared.sandbox.resolve(&target) {
+        Ok(target) => {
+            let mut paths = vec![target.rel.as_str()];
+            if target.real != target.rel {
+                paths.push(target.real.as_str());
+            }
+            acl::check(&shared.acl, &peer.subject(), acl::Operation::Put, &paths).err()
+                .map(|reason| format!("Symlink target not writable: {}", reason))
+        }
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+/// Read a length-prefixed JSON request
+async fn read_request(recv: &mut quinn::RecvStream) -> Result<Request> {
     let mut len_buf = [0u8; 4];
     recv.read_exact(&mut len_buf).await?;
     let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_REQUEST {
+        anyhow::bail!("Request too large: {} bytes", len);
+    }
     
     let mut req_buf = vec![0u8; len];
     recv.read_exact(&mut req_buf).await?;
     
     let request: Request = serde_json::from_slice(&req_buf)?;
     tracing::debug!("Request: {:?}", request);
+    Ok(request)
+}
+
+async fn handle_stream(
+    mut send: quinn::SendStream,
+    mut recv: quinn::RecvStream,
+    shared: &Shared,
+    peer: &Peer,
+) -> Result<()> {
+    let sandbox = &shared.sandbox;
+    let storage = &shared.storage;
+    let audit_tx = &shared.audit_tx;
+    
+    let request = read_request(&mut recv).await?;
+    
+    // Refuse paths that would leave the root before dispatching
+    let mut resolved = Vec::new();
+    for path in request.paths() {
+        match sandbox.resolve(path) {
+            Ok(safe) => resolved.push(safe),
+            Err(e) => {
+                tracing::warn!("⛔ {}", e);
+                let _ = audit_tx.send(peer.audit(AuditEvent::FileRejected)
+                    .with_path(path)
+                    .with_success(false)
+                    .with_message(e.to_string())).await;
+                send_response(&mut send, Response::Error { message: e.to_string() }).await?;
+                return Ok(());
+            }
+        }
+    }
+    
+    // Then check what this client may do there, and where a symlink leads
+    let denied = acl::operations(&request).into_iter().find_map(|(i, op)| {
+        let mut paths: Vec<&str> = resolved.get(i).map(|s| s.rel.as_str()).into_iter().collect();
+        paths.extend(resolved.get(i).map(|s| s.real.as_str()).filter(|real| !paths.contains(real)));
+        acl::check(&shared.acl, &peer.subject(), op, &paths).err().map(|reason| (i, reason))
+    });
+    let denied = denied.or_else(|| match (&request, resolved.first()) {
+        (Request::Put { meta: Some(FileMeta { symlink: Some(target), .. }), .. }, Some(link)) => {
+            link_denied(shared, peer, link, target).map(|reason| (0, reason))
+        }
+        _ => None,
+    });
+    if let Some((i, reason)) = denied {
+        tracing::warn!("⛔ {}", reason);
+        let mut entry = peer.audit(AuditEvent::FileRejected)
+            .with_success(false)
+            .with_message(reason.clone());
+        if let Some(path) = request.paths().get(i) {
+            entry = entry.with_path(*path);
+        }
+        let _ = audit_tx.send(entry).await;
+        send_response(&mut send, Response::Error { message: reason }).await?;
+        return Ok(());
+    }
+    
+    // Handlers work on the paths checked above instead of resolving them again
+    let mut resolved = resolved.into_iter();
+    let mut checked = || resolved.next().ok_or_else(|| anyhow::anyhow!("Request is missing a path"));
     
     match request {
-        Request::Put { path, size, hash } => {
-            let result = handle_put(&mut send, &mut recv, root, &path, size, hash.as_deref()).await;
-            let success = result.is_ok();
-            let _ = audit_tx.send(AuditEntry::new(AuditEvent::FileReceived)
-                .with_remote(remote)
+        Request::Put { path, size, hash, offset, meta, compression } => {
+            let link = meta.as_ref().and_then(|m| m.symlink.as_deref());
+            let result = match link {
+                Some(target) => handle_symlink(&mut send, storage, checked()?, target).await,
+                None => {
+                    let upload = Upload {
+                        size,
+                        hash: hash.as_deref(),
+                        meta: meta.as_ref(),
+                        compression,
+                        link_objects: acl::reads_everything(&shared.acl, &peer.subject()),
+                    };
+                    handle_put(&mut send, &mut recv, storage, checked()?, &upload, offset).await
+                }
+            };
+            let entry = match &result {
+                Ok(PutOutcome::Written) => peer.audit(AuditEvent::FileReceived)
+                    .with_message(if link.is_some() { "OK (symlink)" } else { "OK" }),
+                Ok(PutOutcome::Identical) => peer.audit(AuditEvent::FileReceived)
+                    .with_message("OK (identical)"),
+                Ok(PutOutcome::Rejected(reason)) => peer.audit(AuditEvent::FileRejected)
+                    .with_success(false)
+                    .with_message(reason.clone()),
+                Err(e) => peer.audit(AuditEvent::FileReceived)
+                    .with_success(false)
+                    .with_message(format!("{:?}", e)),
+            };
+            let _ = audit_tx.send(entry
+                .with_path(&path)
+                .with_size(size)).await;
+            result?;
+        }
+        Request::Signature { path } => {
+            let _ = audit_tx.send(peer.audit(AuditEvent::SignatureRequest)
+                .with_path(&path)).await;
+            handle_signature(&mut send, checked()?).await?;
+        }
+        Request::Delta { path, size, hash, block_size, meta } => {
+            let upload = Upload {
+                size,
+                hash: Some(&hash),
+                meta: meta.as_ref(),
+                compression: None,
+                link_objects: acl::reads_everything(&shared.acl, &peer.subject()),
+            };
+            let result = handle_delta(&mut send, &mut recv, storage, checked()?, &upload, block_size).await;
+            let entry = match &result {
+                Ok(PutOutcome::Written) => peer.audit(AuditEvent::FileReceived)
+                    .with_message("OK (delta)"),
+                Ok(PutOutcome::Identical) => peer.audit(AuditEvent::FileReceived)
+                    .with_message("OK (identical)"),
+                Ok(PutOutcome::Rejected(reason)) => peer.audit(AuditEvent::FileRejected)
+                    .with_success(false)
+                    .with_message(reason.clone()),
+                Err(e) => peer.audit(AuditEvent::FileReceived)
+                    .with_success(false)
+                    .with_message(format!("{:?}", e)),
+            };
+            let _ = audit_tx.send(entry
                 .with_path(&path)
-                .with_size(size)
-                .with_success(success)
-                .with_message(if success { "OK".to_string() } else { format!("{:?}", result) })).await;
+                .with_size(size)).await;
             result?;
         }
+        Request::Resume { path, size, hash } => {
+            let _ = audit_tx.send(peer.audit(AuditEvent::ResumeRequest)
+                .with_path(&path)
+                .with_size(size)).await;
+            handle_resume(&mut send, checked()?, size, &hash).await?;
+        }
         Request::List { path, recursive, long } => {
-            let _ = audit_tx.send(AuditEntry::new(AuditEvent::ListRequest)
-                .with_remote(remote)
+            let _ = audit_tx.send(peer.audit(AuditEvent::ListRequest)
+                .with_path(&path)).await;
+            handle_list(&mut send, sandbox, checked()?, recursive, long).await?;
+        }
+        Request::Manifest { path, hash } => {
+            let _ = audit_tx.send(peer.audit(AuditEvent::ManifestRequest)
                 .with_path(&path)).await;
-            handle_list(&mut send, root, &path, recursive, long).await?;
+            handle_manifest(&mut send, sandbox, checked()?, hash).await?;
+        }
+        Request::Delete { path, recursive } => {
+            let result = handle_delete(&mut send, sandbox, storage, checked()?, recursive).await;
+            let mut entry = peer.audit(AuditEvent::FileDeleted).with_path(&path);
+            if recursive {
+                entry = entry.with_message("recursive");
+            }
+            audit_result(audit_tx, entry, &mut send, result).await?;
+        }
+        Request::Rename { from, to } => {
+            let result = handle_rename(&mut send, sandbox, checked()?, checked()?).await;
+            let entry = peer.audit(AuditEvent::FileRenamed)
+                .with_path(&from)
+                .with_message(format!("-> {}", to));
+            audit_result(audit_tx, entry, &mut send, result).await?;
+        }
+        Request::Mkdir { path } => {
+            let result = handle_mkdir(&mut send, checked()?).await;
+            let entry = peer.audit(AuditEvent::DirCreated).with_path(&path);
+            audit_result(audit_tx, entry, &mut send, result).await?;
+        }
+        Request::Versions { path } => {
+            let _ = audit_tx.send(peer.audit(AuditEvent::VersionsRequest)
+                .with_path(&path)).await;
+            handle_versions(&mut send, &storage.versions, checked()?).await?;
+        }
+        Request::Restore { path, version } => {
+            let result = handle_restore(&mut send, storage, checked()?, &version).await;
+            let entry = peer.audit(AuditEvent::FileRestored)
+                .with_path(&path)
+                .with_message(version);
+            audit_result(audit_tx, entry, &mut send, result).await?;
         }
         Request::Status => {
-            let _ = audit_tx.send(AuditEntry::new(AuditEvent::StatusRequest)
-                .with_remote(remote)).await;
-            handle_status(&mut send, root).await?;
+            let _ = audit_tx.send(peer.audit(AuditEvent::StatusRequest)).await;
+            handle_status(&mut send, sandbox).await?;
         }
-        Request::Get { path } => {
-            let _ = audit_tx.send(AuditEntry::new(AuditEvent::FileRequest)
-                .with_remote(remote)
+        Request::Get { path, offset, length, meta, compression } => {
+            let _ = audit_tx.send(peer.audit(AuditEvent::FileRequest)
                 .with_path(&path)).await;
-            handle_get(&mut send, root, &path).await?;
+            handle_get(&mut send, checked()?, offset, length, meta, compression).await?;
+        }
+        Request::Auth { .. } => {
+            // Either no tokens are configured or the client already has a
+            // certificate; a token check only ever happens on the first stream
+            let response = if shared.tokens.is_empty() || peer.identity.is_some() {
+                Response::Ok
+            } else {
+                Response::Error { message: "Authentication must be the first request".to_string() }
+            };
+            send_response(&mut send, response).await?;
         }
     }
     
     Ok(())
 }
 
+/// Result of an upload that completed at the protocol level
+enum PutOutcome {
+    /// File stored
+    Written,
+    /// Content was already there, nothing transferred
+    Identical,
+    /// Upload refused (e.g. hash mismatch), partial file removed
+    Rejected(String),
+}
+
+/// What the client declared about an upload
+struct Upload<'a> {
+    size: u64,
+    hash: Option<&'a str>,
+    meta: Option<&'a FileMeta>,
+    /// How the data on the stream is compressed
+    compression: Option<Compression>,
+    /// The client may read the whole root, so it may be given content
+    /// from the object store that it did not send
+    link_objects: bool,
+}
+
 async fn handle_put(
     send: &mut quinn::SendStream,
     recv: &mut quinn::RecvStream,
-    root: &Path,
-    path: &str,
-    size: u64,
-    _hash: Option<&str>,
-) -> Result<()> {
-    // Sanitize path (no ..)
-    let clean_path = path.trim_start_matches('/').replace("..", "");
-    let dest = root.join(&clean_path);
+    storage: &Storage,
+    path: SafePath,
+    upload: &Upload<'_>,
+    offset: u64,
+) -> Result<PutOutcome> {
+    let Upload { size, hash, .. } = *upload;
+    let SafePath { rel: clean_path, full: dest, .. } = path;
     
     // Create parent directories
     if let Some(parent) = dest.parent() {
         fs::create_dir_all(parent).await?;
     }
     
-    tracing::info!("📝 Receiving: {} ({} bytes)", clean_path, size);
+    if skip_identical(send, storage, &clean_path, &dest, upload).await? {
+        return Ok(PutOutcome::Identical);
+    }
+    
+    // Receive into a hidden sibling so the previous copy survives a failed transfer.
+    // Uploads with a declared hash get a stable name so they can be resumed.
+    let temp = match hash {
+        Some(h) => resumable_part_path(&dest, h),
+        None => part_path(&dest),
+    };
+    
+    if offset > 0 {
+        let held = fs::metadata(&temp).await.map(|m| m.len()).unwrap_or(0);
+        let reason = if hash.is_none() {
+            Some("Resume requires a hash".to_string())
+        } else if offset > size || offset > held {
+            Some(format!("Cannot resume at {}: server holds {} of {} bytes", offset, held, size))
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            send_response(send, Response::Error { message: reason.clone() }).await?;
+            return Ok(PutOutcome::Rejected(reason));
+        }
+        tracing::info!("📝 Resuming: {} at {} of {} bytes", clean_path, offset, size);
+    } else {
+        tracing::info!("📝 Receiving: {} ({} bytes)", clean_path, size);
+    }
     
     // Send OK to start transfer
     send_response(send, Response::Ok).await?;
     
-    // Receive file data
-    let mut file = fs::File::create(&dest).await?;
-    let mut received = 0u64;
+    let mut body = compress::decoder(&mut *recv, upload.compression);
+    let received = match receive_into(&mut body, &temp, offset, size).await {
+        Ok(r) => r,
+        Err(e) => {
+            // Keep resumable partials around for the next attempt
+            if hash.is_none() {
+                let _ = fs::remove_file(&temp).await;
+            }
+            return Err(e);
+        }
+    };
+    
+    finish_upload(send, storage, &clean_path, &temp, &dest, upload, received).await
+}
+
+/// Create (or replace) a symlink; its target must stay inside the root
+async fn handle_symlink(
+    send: &mut quinn::SendStream,
+    storage: &Storage,
+    path: SafePath,
+    target: &str,
+) -> Result<PutOutcome> {
+    let SafePath { rel: clean_path, full: dest, .. } = path;
+    if let Err(e) = sandbox::check_link_target(&clean_path, target) {
+        let reason = e.to_string();
+        send_response(send, Response::Error { message: reason.clone() }).await?;
+        return Ok(PutOutcome::Rejected(reason));
+    }
+    
+    #[cfg(unix)]
+    {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let temp = part_path(&dest);
+        fs::symlink(target, &temp).await?;
+        if let Err(e) = storage.retire(&clean_path, &dest) {
+            let _ = fs::remove_file(&temp).await;
+            return Err(e);
+        }
+        if let Err(e) = fs::rename(&temp, &dest).await {
+            let _ = fs::remove_file(&temp).await;
+            return Err(e.into());
+        }
+        tracing::info!("🔗 Linked: {} -> {}", clean_path, target);
+        send_response(send, Response::Done { written: 0 }).await?;
+        Ok(PutOutcome::Written)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dest;
+        let reason = "Symlinks are not supported on this server".to_string();
+        send_response(send, Response::Error { message: reason.clone() }).await?;
+        Ok(PutOutcome::Rejected(reason))
+    }
+}
+
+/// Answer `Identical` instead of receiving the upload if `dest` already has
+/// the declared content, or if the object store has it and it can be linked
+/// into place. Returns `true` if the client need not send anything.
+async fn skip_identical(
+    send: &mut quinn::SendStream,
+    storage: &Storage,
+    clean_path: &str,
+    dest: &Path,
+    upload: &Upload<'_>,
+) -> Result<bool> {
+    let Upload { size, hash: Some(hash), meta, link_objects, .. } = *upload else {
+        return Ok(false);
+    };
+    
+    let same_size = fs::metadata(dest).await.is_ok_and(|m| m.is_file() && m.len() == size);
+    let in_place = same_size && {
+        let dest = dest.to_path_buf();
+        let existing = tokio::task::spawn_blocking(move || -> std::io::Result<String> {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_reader(std::fs::File::open(&dest)?)?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }).await?;
+        // Unreadable counts as different, the upload then replaces it
+        existing.is_ok_and(|existing| existing.eq_ignore_ascii_case(hash))
+    };
+    
+    if in_place {
+        // Shared objects keep the attributes of their first upload
+        if let Some(meta) = meta
+            && !storage.objects.is_enabled()
+            && let Err(e) = meta.on_server(false).apply(dest)
+        {
+            tracing::warn!("Could not apply attributes to {}: {}", clean_path, e);
+        }
+    } else {
+        // Linking tells whether the content exists anywhere on the server and
+        // hands it out, so only for clients that could read it anyway
+        if !link_objects {
+            return Ok(false);
+        }
+        let temp = part_path(dest);
+        if !tokio::task::block_in_place(|| storage.objects.link_to(hash, &temp))? {
+            return Ok(false);
+        }
+        let linked = storage.retire(clean_path, dest)
+            .and_then(|_| std::fs::rename(&temp, dest).map_err(Into::into));
+        if let Err(e) = linked {
+            let _ = fs::remove_file(&temp).await;
+            return Err(e);
+        }
+    }
+    
+    // A partial from an interrupted attempt is no longer needed
+    let _ = fs::remove_file(resumable_part_path(dest, hash)).await;
+    tracing::info!("⏭️  Identical: {}{}", clean_path, if in_place { "" } else { " (linked)" });
+    send_response(send, Response::Identical).await?;
+    Ok(true)
+}
+
+/// Bytes and blake3 hash of a fully received temp file
+struct Received {
+    bytes: u64,
+    hash: String,
+}
+
+/// Verify a received temp file against the declared size/hash and move it into place
+async fn finish_upload(
+    send: &mut quinn::SendStream,
+    storage: &Storage,
+    clean_path: &str,
+    temp: &Path,
+    dest: &Path,
+    upload: &Upload<'_>,
+    received: Received,
+) -> Result<PutOutcome> {
+    let Upload { size, hash, meta, .. } = *upload;
+    let rejection = if received.bytes != size {
+        Some(format!("Incomplete transfer: expected {} bytes, got {}", size, received.bytes))
+    } else {
+        match hash {
+            Some(expected) if !expected.eq_ignore_ascii_case(&received.hash) => {
+                // Not what was received: with a delta that would reveal the server's data
+                Some(format!("Hash mismatch: content does not match {}", expected))
+            }
+            _ => None,
+        }
+    };
+    
+    if let Some(reason) = rejection {
+        tracing::warn!("❌ Rejected: {} ({})", clean_path, reason);
+        let _ = fs::remove_file(temp).await;
+        send_response(send, Response::Error { message: reason.clone() }).await?;
+        return Ok(PutOutcome::Rejected(reason));
+    }
+    
+    // Attributes go onto the temp file so the rename publishes them atomically
+    if let Some(meta) = meta
+        && let Err(e) = meta.on_server(storage.objects.is_enabled()).apply(temp)
+    {
+        tracing::warn!("Could not apply attributes to {}: {}", clean_path, e);
+    }
+    
+    // Identical content shares one object (and with it mtime and permissions)
+    match tokio::task::block_in_place(|| storage.objects.store(temp, &received.hash)) {
+        Ok(true) => tracing::info!("🧬 Deduplicated: {}", clean_path),
+        Ok(false) => {}
+        Err(e) => tracing::warn!("Could not deduplicate {}: {}", clean_path, e),
+    }
+    
+    // Keep the copy we are about to replace
+    match storage.retire(clean_path, dest) {
+        Ok(Some(id)) => tracing::info!("🕘 Kept version {} of {}", id, clean_path),
+        Ok(None) => {}
+        Err(e) => {
+            let _ = fs::remove_file(temp).await;
+            return Err(e.context(format!("Could not keep previous version of {}", clean_path)));
+        }
+    }
+    
+    if let Err(e) = fs::rename(temp, dest).await {
+        let _ = fs::remove_file(temp).await;
+        return Err(e.into());
+    }
+    
+    tracing::info!("✅ Written: {} ({} bytes)", clean_path, received.bytes);
+    
+    // Send completion
+    send_response(send, Response::Done { written: received.bytes }).await?;
+    
+    Ok(PutOutcome::Written)
+}
+
+/// Block signatures of an existing file, empty if there is nothing to diff against
+async fn handle_signature(
+    send: &mut quinn::SendStream,
+    path: SafePath,
+) -> Result<()> {
+    let SafePath { rel: clean_path, full: file_path, .. } = path;
+    
+    let size = match fs::metadata(&file_path).await {
+        Ok(m) if m.is_file() => m.len(),
+        _ => {
+            send_response(send, Response::Signature { size: 0, block_size: 0, blocks: Vec::new() }).await?;
+            return Ok(());
+        }
+    };
+    
+    let block_size = delta::block_size_for(size);
+    let blocks = tokio::task::spawn_blocking(move || {
+        delta::signature(std::io::BufReader::new(std::fs::File::open(&file_path)?), block_size)
+    }).await??;
+    
+    tracing::info!("🧮 Signature: {} ({} blocks of {} bytes)", clean_path, blocks.len(), block_size);
+    send_response(send, Response::Signature { size, block_size, blocks }).await?;
+    
+    Ok(())
+}
+
+/// Rebuild a file from the existing copy plus a stream of delta ops
+async fn handle_delta(
+    send: &mut quinn::SendStream,
+    recv: &mut quinn::RecvStream,
+    storage: &Storage,
+    path: SafePath,
+    upload: &Upload<'_>,
+    block_size: u32,
+) -> Result<PutOutcome> {
+    let size = upload.size;
+    let SafePath { rel: clean_path, full: dest, .. } = path;
+    
+    let base_len = match fs::metadata(&dest).await {
+        Ok(m) if m.is_file() => m.len(),
+        _ => {
+            let reason = "Delta needs an existing file on the server".to_string();
+            send_response(send, Response::Error { message: reason.clone() }).await?;
+            return Ok(PutOutcome::Rejected(reason));
+        }
+    };
+    // Only the size the signature was made with; it also bounds the block buffer
+    let expected = delta::block_size_for(base_len);
+    if block_size != expected {
+        let reason = format!("Invalid block size {}: expected {}", block_size, expected);
+        send_response(send, Response::Error { message: reason.clone() }).await?;
+        return Ok(PutOutcome::Rejected(reason));
+    }
+    if skip_identical(send, storage, &clean_path, &dest, upload).await? {
+        return Ok(PutOutcome::Identical);
+    }
+    
+    tracing::info!("📝 Receiving delta: {} ({} bytes)", clean_path, size);
+    send_response(send, Response::Ok).await?;
+    
+    let temp = part_path(&dest);
+    let received = match apply_delta(recv, &dest, base_len, &temp, block_size).await {
+        Ok(r) => r,
+        Err(e) => {
+            let _ = fs::remove_file(&temp).await;
+            return Err(e);
+        }
+    };
+    
+    finish_upload(send, storage, &clean_path, &temp, &dest, upload, received).await
+}
+
+/// Write `temp` from delta ops, copying referenced blocks out of `base`
+async fn apply_delta(
+    recv: &mut quinn::RecvStream,
+    base: &Path,
+    base_len: u64,
+    temp: &Path,
+    block_size: u32,
+) -> Result<Received> {
+    let mut base_file = fs::File::open(base).await?;
+    let mut file = fs::File::create(temp).await?;
+    let mut hasher = blake3::Hasher::new();
+    let mut written = 0u64;
+    let mut block = vec![0u8; block_size as usize];
+    
+    while let Some(op) = delta::read_op(recv).await? {
+        let data = match op {
+            delta::Op::Copy(index) => {
+                let start = index as u64 * block_size as u64;
+                if start >= base_len {
+                    anyhow::bail!("Delta references block {} beyond end of file", index);
+                }
+                let len = std::cmp::min(block_size as u64, base_len - start) as usize;
+                base_file.seek(std::io::SeekFrom::Start(start)).await?;
+                base_file.read_exact(&mut block[..len]).await?;
+                &block[..len]
+            }
+            delta::Op::Literal(ref bytes) => &bytes[..],
+        };
+        file.write_all(data).await?;
+        hasher.update(data);
+        written += data.len() as u64;
+    }
+    
+    file.flush().await?;
+    file.sync_all().await?;
+    
+    Ok(Received { bytes: written, hash: hasher.finalize().to_hex().to_string() })
+}
+
+/// Tell the client how many bytes of a resumable upload we already hold
+async fn handle_resume(
+    send: &mut quinn::SendStream,
+    path: SafePath,
+    size: u64,
+    hash: &str,
+) -> Result<()> {
+    let SafePath { rel: clean_path, full: dest, .. } = path;
+    let temp = resumable_part_path(&dest, hash);
+    
+    let held = fs::metadata(&temp).await.map(|m| m.len()).unwrap_or(0);
+    let offset = held.min(size);
+    if offset > 0 {
+        tracing::info!("↻ Partial upload of {}: {} of {} bytes", clean_path, offset, size);
+    }
+    
+    send_response(send, Response::Offset { offset }).await?;
+    
+    Ok(())
+}
+
+/// Stream bytes `offset..size` into `temp`, fsync it and return its total size and hash.
+/// With a non-zero offset the first `offset` bytes already in `temp` are kept and re-hashed.
+async fn receive_into<R: tokio::io::AsyncRead + Unpin>(
+    recv: &mut R,
+    temp: &Path,
+    offset: u64,
+    size: u64,
+) -> Result<Received> {
+    let mut hasher = blake3::Hasher::new();
     let mut buf = vec![0u8; 64 * 1024]; // 64KB chunks
     
+    let mut file = if offset > 0 {
+        let mut file = fs::OpenOptions::new().read(true).write(true).open(temp).await?;
+        file.set_len(offset).await?;
+        let mut remaining = offset;
+        while remaining > 0 {
+            let to_read = std::cmp::min(buf.len() as u64, remaining) as usize;
+            let n = file.read(&mut buf[..to_read]).await?;
+            if n == 0 {
+                anyhow::bail!("Partial upload shrank while resuming: {:?}", temp);
+            }
+            hasher.update(&buf[..n]);
+            remaining -= n as u64;
+        }
+        file
+    } else {
+        fs::File::create(temp).await?
+    };
+    
+    let mut received = offset;
+    
     while received < size {
         let to_read = std::cmp::min(buf.len() as u64, size - received) as usize;
-        let n = recv.read(&mut buf[..to_read]).await?.unwrap_or(0);
+        let n = recv.read(&mut buf[..to_read]).await?;
         if n == 0 {
             break;
         }
         file.write_all(&buf[..n]).await?;
+        hasher.update(&buf[..n]);
         received += n as u64;
     }
     
     file.flush().await?;
-    drop(file);
+    file.sync_all().await?;
     
-    tracing::info!("✅ Written: {} ({} bytes)", clean_path, received);
-    
-    // Send completion
-    send_response(send, Response::Done { written: received }).await?;
-    
-    Ok(())
+    Ok(Received { bytes: received, hash: hasher.finalize().to_hex().to_string() })
+}
+
+/// Temporary upload path next to `dest`: `.<name>.<nonce>.hank-part`
+fn part_path(dest: &Path) -> PathBuf {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    dest.with_file_name(format!(".{}.{:x}{}", name, nonce, PART_SUFFIX))
+}
+
+/// Stable partial path for uploads with a declared hash: `.<name>.<hash16>.hank-part`
+fn resumable_part_path(dest: &Path, hash: &str) -> PathBuf {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let key: String = hash.chars().take(16).collect::<String>().to_ascii_lowercase();
+    dest.with_file_name(format!(".{}.{}{}", name, key, PART_SUFFIX))
+}
+
+fn is_partial(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(PART_SUFFIX)
+}
+
+/// Remove temporary upload files that nobody resumed within `PARTIAL_MAX_AGE`
+async fn cleanup_partials(root: &Path) -> usize {
+    let mut removed = 0;
+    for entry in walkdir::WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() || !is_partial(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let age = entry.metadata().ok()
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.elapsed().ok())
+            .unwrap_or_default();
+        if age >= PARTIAL_MAX_AGE {
+            match fs::remove_file(entry.path()).await {
+                Ok(()) => removed += 1,
+                Err(e) => tracing::warn!("Could not remove stale upload {:?}: {}", entry.path(), e),
+            }
+        }
+    }
+    removed
 }
 
 async fn handle_list(
     send: &mut quinn::SendStream,
-    root: &Path,
-    path: &str,
+    sandbox: &Sandbox,
+    path: SafePath,
     recursive: bool,
     long: bool,
 ) -> Result<()> {
-    let clean_path = path.trim_start_matches('/').replace("..", "");
-    let dir = if clean_path.is_empty() {
-        root.to_path_buf()
-    } else {
-        root.join(&clean_path)
-    };
+    let dir = path.full;
     
     let mut entries = Vec::new();
     
     if dir.is_dir() {
         if recursive {
-            for entry in walkdir::WalkDir::new(&dir).into_iter().filter_map(|e| e.ok()) {
+            let walk = walkdir::WalkDir::new(&dir).into_iter()
+                .filter_entry(|e| !sandbox.is_reserved(e.path()));
+            for entry in walk.filter_map(|e| e.ok()) {
                 if entry.path() == dir { continue; }
+                if is_partial(&entry.file_name().to_string_lossy()) { continue; }
                 let metadata = entry.metadata().ok();
                 let rel = entry.path().strip_prefix(&dir).unwrap_or(entry.path());
                 let name = rel.to_string_lossy().replace('\\', "/");
@@ -229,8 +1017,9 @@ async fn handle_list(
         } else {
             let mut read_dir = fs::read_dir(&dir).await?;
             while let Some(entry) = read_dir.next_entry().await? {
-                let meta = entry.metadata().await?;
                 let name = entry.file_name().to_string_lossy().to_string();
+                if is_partial(&name) || sandbox.is_reserved(&entry.path()) { continue; }
+                let meta = entry.metadata().await?;
                 let modified = if long {
                     meta.modified().ok().and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok()).map(|d| d.as_secs())
                 } else { None };
@@ -249,25 +1038,233 @@ async fn handle_list(
     Ok(())
 }
 
+async fn handle_manifest(
+    send: &mut quinn::SendStream,
+    sandbox: &Sandbox,
+    path: SafePath,
+    hash: bool,
+) -> Result<()> {
+    let dir = path.full;
+    if dir.exists() && !dir.is_dir() {
+        send_response(send, Response::Error { message: "Not a directory".into() }).await?;
+        return Ok(());
+    }
+    
+    // Hashing a large tree takes a while, keep it off the runtime
+    let sandbox = sandbox.clone();
+    let entries = tokio::task::spawn_blocking(move || -> Result<Vec<ManifestEntry>> {
+        let mut entries = Vec::new();
+        if !dir.is_dir() {
+            return Ok(entries);
+        }
+        let walk = walkdir::WalkDir::new(&dir).into_iter()
+            .filter_entry(|e| !sandbox.is_reserved(e.path()));
+        for entry in walk.filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() || is_partial(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+            let hash = if hash {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update_reader(std::fs::File::open(entry.path())?)?;
+                Some(hasher.finalize().to_hex().to_string())
+            } else {
+                None
+            };
+            let rel = entry.path().strip_prefix(&dir)?;
+            entries.push(ManifestEntry {
+                path: rel.to_string_lossy().replace('\\', "/"),
+                size: metadata.len(),
+                modified,
+                hash,
+            });
+        }
+        Ok(entries)
+    }).await??;
+    
+    send_response(send, Response::Manifest { entries }).await?;
+    Ok(())
+}
+
+/// Audit a management request; failures are reported to the client too
+async fn audit_result(
+    audit_tx: &mpsc::Sender<AuditEntry>,
+    entry: AuditEntry,
+    send: &mut quinn::SendStream,
+    result: Result<()>,
+) -> Result<()> {
+    let entry = match &result {
+        Ok(()) => entry,
+        Err(e) => entry.with_success(false).with_message(e.to_string()),
+    };
+    let _ = audit_tx.send(entry).await;
+    if let Err(e) = result {
+        send_response(send, Response::Error { message: e.to_string() }).await?;
+    }
+    Ok(())
+}
+
+/// Remove a file or directory (non-empty ones only if `recursive`)
+async fn handle_delete(
+    send: &mut quinn::SendStream,
+    sandbox: &Sandbox,
+    storage: &Storage,
+    path: SafePath,
+    recursive: bool,
+) -> Result<()> {
+    let SafePath { rel, full, .. } = path;
+    if rel.is_empty() {
+        anyhow::bail!("Refusing to delete the root");
+    }
+    if sandbox.holds_reserved(&rel) {
+        anyhow::bail!("Refusing to delete {}: it holds files of the server", rel);
+    }
+    let metadata = fs::symlink_metadata(&full).await
+        .map_err(|_| anyhow::anyhow!("No such file or directory: {}", rel))?;
+    if metadata.is_dir() {
+        let removed = if recursive {
+            // Every file is replaced by nothing: keep a version of each
+            for entry in walkdir::WalkDir::new(&full).into_iter().filter_map(|e| e.ok()) {
+                if entry.file_type().is_dir() { continue; }
+                let inner = entry.path().strip_prefix(&full).unwrap_or(entry.path());
+                let file_rel = format!("{}/{}", rel, inner.to_string_lossy().replace('\\', "/"));
+                storage.retire(&file_rel, entry.path())?;
+            }
+            // Does not follow symlinks, so nothing outside the tree is touched
+            fs::remove_dir_all(&full).await
+        } else {
+            fs::remove_dir(&full).await
+        };
+        removed.map_err(|e| anyhow::anyhow!("Cannot remove directory {}: {}", rel, e))?;
+    } else {
+        storage.retire(&rel, &full)?;
+        fs::remove_file(&full).await?;
+    }
+    tracing::info!("🗑️  Deleted: {}", rel);
+    send_response(send, Response::Ok).await?;
+    Ok(())
+}
+
+/// Move `from` to `to`; into `to` if that is an existing directory
+async fn handle_rename(send: &mut quinn::SendStream, sandbox: &Sandbox, from: SafePath, to: SafePath) -> Result<()> {
+    let SafePath { rel: from_rel, full: from_full, .. } = from;
+    let SafePath { rel: mut to_rel, full: mut to_full, .. } = to;
+    if from_rel.is_empty() {
+        anyhow::bail!("Refusing to move the root");
+    }
+    if sandbox.holds_reserved(&from_rel) {
+        anyhow::bail!("Refusing to move {}: it holds files of the server", from_rel);
+    }
+    if fs::symlink_metadata(&from_full).await.is_err() {
+        anyhow::bail!("No such file or directory: {}", from_rel);
+    }
+    
+    if to_full.is_dir() {
+        let name = from_full.file_name().unwrap_or_default().to_string_lossy().to_string();
+        to_rel = if to_rel.is_empty() { name.clone() } else { format!("{}/{}", to_rel, name) };
+        to_full = to_full.join(name);
+    }
+    if to_rel == from_rel || to_rel.starts_with(&format!("{}/", from_rel)) {
+        anyhow::bail!("Cannot move {} into itself", from_rel);
+    }
+    if fs::symlink_metadata(&to_full).await.is_ok() {
+        anyhow::bail!("Destination exists: {}", to_rel);
+    }
+    
+    if let Some(parent) = to_full.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::rename(&from_full, &to_full).await?;
+    tracing::info!("📦 Moved: {} -> {}", from_rel, to_rel);
+    send_response(send, Response::Ok).await?;
+    Ok(())
+}
+
+async fn handle_mkdir(send: &mut quinn::SendStream, path: SafePath) -> Result<()> {
+    let SafePath { rel, full, .. } = path;
+    if full.exists() && !full.is_dir() {
+        anyhow::bail!("Not a directory: {}", rel);
+    }
+    fs::create_dir_all(&full).await?;
+    tracing::info!("📁 Created: {}", rel);
+    send_response(send, Response::Ok).await?;
+    Ok(())
+}
+
+async fn handle_versions(send: &mut quinn::SendStream, versions: &VersionStore, path: SafePath) -> Result<()> {
+    let rel = path.rel;
+    let response = match versions.list(&rel) {
+        Ok(versions) => Response::Versions { versions },
+        Err(e) => Response::Error { message: e.to_string() },
+    };
+    send_response(send, response).await?;
+    Ok(())
+}
+
+/// Put a copy of version `id` in place of the current file
+async fn handle_restore(
+    send: &mut quinn::SendStream,
+    storage: &Storage,
+    path: SafePath,
+    id: &str,
+) -> Result<()> {
+    let SafePath { rel, full, .. } = path;
+    let version = storage.versions.path_of(&rel, id)?;
+    if full.is_dir() {
+        anyhow::bail!("Not a file: {}", rel);
+    }
+    
+    if let Some(parent) = full.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    // Link (or copy), so the version stays available; the rename replaces atomically
+    let temp = part_path(&full);
+    let restored = async {
+        if fs::hard_link(&version, &temp).await.is_err() {
+            fs::copy(&version, &temp).await?;
+            let modified = fs::metadata(&version).await?.modified()?;
+            std::fs::File::options().write(true).open(&temp)?.set_modified(modified)?;
+        }
+        storage.retire(&rel, &full)?;
+        fs::rename(&temp, &full).await?;
+        anyhow::Ok(())
+    }.await;
+    if restored.is_err() {
+        let _ = fs::remove_file(&temp).await;
+    }
+    restored?;
+    
+    tracing::info!("🕘 Restored: {} from version {}", rel, id);
+    send_response(send, Response::Ok).await?;
+    Ok(())
+}
+
 async fn handle_status(
     send: &mut quinn::SendStream,
-    root: &Path,
+    sandbox: &Sandbox,
 ) -> Result<()> {
     // Calculate disk usage
     let mut total_size = 0u64;
     let mut file_count = 0u64;
     
-    for entry in walkdir::WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
-        if entry.file_type().is_file() {
+    let root = sandbox.root();
+    let walk = walkdir::WalkDir::new(root).into_iter()
+        .filter_entry(|e| !sandbox.is_reserved(e.path()));
+    for entry in walk.filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() && !is_partial(&entry.file_name().to_string_lossy()) {
             total_size += entry.metadata().map(|m| m.len()).unwrap_or(0);
             file_count += 1;
         }
     }
     
+    let physical_size = ObjectStore::physical_size(root);
+    
     send_response(send, Response::Status {
         root: root.to_string_lossy().to_string(),
         total_size,
         file_count,
+        physical_size,
     }).await?;
     
     Ok(())
@@ -275,34 +1272,91 @@ async fn handle_status(
 
 async fn handle_get(
     send: &mut quinn::SendStream,
-    root: &Path,
-    path: &str,
+    path: SafePath,
+    offset: u64,
+    length: Option<u64>,
+    with_meta: bool,
+    compression: Option<Compression>,
 ) -> Result<()> {
-    let clean_path = path.trim_start_matches('/').replace("..", "");
-    let file_path = root.join(&clean_path);
+    let SafePath { rel, full: file_path, .. } = path;
 
-    let metadata = fs::metadata(&file_path).await?;
+    // Report symlinks as such instead of following them
+    let is_link = fs::symlink_metadata(&file_path).await.is_ok_and(|m| m.is_symlink());
+    if with_meta && is_link {
+        let target = match fs::read_link(&file_path).await {
+            Ok(target) => target,
+            Err(e) => {
+                send_response(send, unreadable(&rel, &e)).await?;
+                return Ok(());
+            }
+        };
+        let meta = FileMeta { symlink: Some(target.to_string_lossy().to_string()), ..Default::default() };
+        send_response(send, Response::File { size: 0, offset: 0, total: 0, modified: 0, meta: Some(meta), compression: None }).await?;
+        return Ok(());
+    }
+
+    let metadata = match fs::metadata(&file_path).await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            send_response(send, unreadable(&rel, &e)).await?;
+            return Ok(());
+        }
+    };
+    if metadata.is_dir() {
+        send_response(send, Response::Directory).await?;
+        return Ok(());
+    }
     if !metadata.is_file() {
         send_response(send, Response::Error { message: "Not a file".into() }).await?;
         return Ok(());
     }
 
-    let size = metadata.len();
-    send_response(send, Response::File { size }).await?;
+    let total = metadata.len();
+    if offset > total {
+        send_response(send, Response::Error {
+            message: format!("Offset {} beyond end of file ({} bytes)", offset, total),
+        }).await?;
+        return Ok(());
+    }
+    let size = length.map_or(total - offset, |l| l.min(total - offset));
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+    let meta = with_meta.then(|| FileMeta::from_metadata(&metadata));
+    // The client names the level it wants, we judge whether the file is worth it
+    let compression = match size {
+        0 => None,
+        _ => compression.and_then(|c| compress::choose(&file_path, Some(c.level))),
+    };
+    // Open before the header goes out, so a failure can still be reported
+    let mut file = match fs::File::open(&file_path).await {
+        Ok(file) => file,
+        Err(e) => {
+            send_response(send, unreadable(&rel, &e)).await?;
+            return Ok(());
+        }
+    };
+    send_response(send, Response::File { size, offset, total, modified, meta, compression }).await?;
 
-    let mut file = fs::File::open(&file_path).await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let mut body = compress::encoder(file.take(size), compression);
     let mut buf = vec![0u8; 64 * 1024];
-    let mut sent = 0u64;
-    while sent < size {
-        let n = file.read(&mut buf).await?;
+    loop {
+        let n = body.read(&mut buf).await?;
         if n == 0 { break; }
         send.write_all(&buf[..n]).await?;
-        sent += n as u64;
     }
 
     Ok(())
 }
 
+/// Error response for a file the client asked for but cannot have
+fn unreadable(rel: &str, e: &std::io::Error) -> Response {
+    let message = match e.kind() {
+        std::io::ErrorKind::NotFound => format!("No such file or directory: {}", rel),
+        _ => format!("Cannot read {}: {}", rel, e),
+    };
+    Response::Error { message }
+}
+
 async fn send_response(send: &mut quinn::SendStream, response: Response) -> Result<()> {
     let json = serde_json::to_vec(&response)?;
     let len = (json.len() as u32).to_be_bytes();
@@ -0,0 +1,379 @@
+//! Two-way directory sync
+//!
+//! Both sides are compared against the state recorded after the previous
+//! run (`sync/<key>.json` in the config directory). That is what tells a
+//! file deleted here apart from one that is new on the other side.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::client;
+use crate::config;
+use crate::progress;
+use crate::protocol::ManifestEntry;
+
+/// Options for `sync`
+#[derive(Debug, Default, Clone)]
+pub struct SyncOptions {
+    /// Compare blake3 hashes when both sides changed (needs read access)
+    pub checksum: bool,
+    /// Only print the plan
+    pub dry_run: bool,
+}
+
+/// State of both sides after the last successful sync
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncDb {
+    server: String,
+    local: PathBuf,
+    remote: String,
+    files: BTreeMap<String, Base>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Base {
+    size: u64,
+    local_modified: u64,
+    remote_modified: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Upload,
+    Download,
+    DeleteRemote,
+    DeleteLocal,
+    Conflict(&'static str),
+    /// Same content on both sides, only remember it
+    Record,
+    /// Gone on both sides
+    Forget,
+}
+
+pub async fn sync(server: &str, local: &Path, remote: &str, opts: &SyncOptions) -> Result<()> {
+    std::fs::create_dir_all(local)?;
+    let local = local.canonicalize()?;
+    let db_path = db_path(server, &local, remote)?;
+    let mut db = load_db(&db_path)?;
+    db.server = server.to_string();
+    db.local = local.clone();
+    db.remote = remote.to_string();
+
+    let connection = client::connect(server).await?;
+    let mut remote_files = index(client::manifest(&connection, remote, opts.checksum).await?);
+    let local_files = scan(&local)?;
+
+    // Files on both sides that were never synced can only be told apart by content
+    let unknown = local_files.iter()
+        .any(|(path, l)| !db.files.contains_key(path) && remote_files.get(path).is_some_and(|r| r.size == l.size));
+    if unknown && !opts.checksum {
+        tracing::info!("Comparing contents of files not synced before");
+        remote_files = index(client::manifest(&connection, remote, true).await?);
+    }
+
+    let paths: BTreeSet<&String> = local_files.keys()
+        .chain(remote_files.keys())
+        .chain(db.files.keys())
+        .collect();
+
+    let mut plan = Vec::new();
+    for path in paths {
+        let action = plan_one(
+            local_files.get(path),
+            remote_files.get(path),
+            db.files.get(path),
+            &local.join(path),
+            &remote_join(remote, path),
+        ).await?;
+        if let Some(action) = action {
+            plan.push((path.clone(), action));
+        }
+    }
+
+    let mut up = 0;
+    let mut down = 0;
+    let mut deleted = 0;
+    let mut conflicts = 0;
+    let mut unsettled = BTreeSet::new();
+    let mut errors = Vec::new();
+    if !opts.dry_run {
+        let transfers: Vec<u64> = plan.iter()
+            .filter_map(|(path, action)| match action {
+                Action::Upload => local_files.get(path),
+                Action::Download => remote_files.get(path),
+                _ => None,
+            })
+            .map(|entry| entry.size)
+            .collect();
+        progress::expect(transfers.len() as u64, transfers.iter().sum());
+    }
+    for (path, action) in &plan {
+        let local_path = local.join(path);
+        let remote_path = remote_join(remote, path);
+        progress::suspend(|| match action {
+            Action::Upload => println!("⬆️  {}", path),
+            Action::Download => println!("⬇️  {}", path),
+            Action::DeleteRemote => println!("🗑️  {} (remote)", path),
+            Action::DeleteLocal => println!("🗑️  {} (local)", path),
+            Action::Conflict(reason) => println!("⚠️  {}: {}", path, reason),
+            Action::Record | Action::Forget => {}
+        });
+        if opts.dry_run {
+            continue;
+        }
+
+        let result = match action {
+            Action::Upload => async {
+                let sealed = client::seal(&local_path, &remote_path).await?;
+                let local_path = sealed.as_ref().map_or(local_path.as_path(), |s| s.path());
+                let size = std::fs::metadata(local_path)?.len();
+                let hash = client::hash_file(local_path).await?;
+                let progress = progress::start(&remote_path, size);
+                client::upload_resumable(&connection, local_path, &remote_path, &hash, None, None, &progress).await?;
+                progress.finish();
+                up += 1;
+                Ok(())
+            }.await,
+            Action::Download => client::download(&connection, &remote_path, &local_path, None, None).await
+                .map(|_| down += 1),
+            Action::DeleteRemote => client::delete(&connection, &remote_path, false).await
+                .map(|()| deleted += 1),
+            Action::DeleteLocal => std::fs::remove_file(&local_path)
+                .map(|()| deleted += 1)
+                .map_err(Into::into),
+            Action::Conflict(_) => {
+                conflicts += 1;
+                unsettled.insert(path.clone());
+                Ok(())
+            }
+            Action::Record | Action::Forget => Ok(()),
+        };
+        if let Err(e) = result {
+            tracing::error!("❌ {}: {:#}", path, e);
+            unsettled.insert(path.clone());
+            errors.push(format!("{}: {:#}", path, e));
+        }
+    }
+
+    if opts.dry_run {
+        println!("(dry run, {} action(s) planned)", plan.iter()
+            .filter(|(_, a)| !matches!(a, Action::Record | Action::Forget))
+            .count());
+        connection.close(0u32.into(), b"done");
+        return Ok(());
+    }
+
+    // Record what both sides look like now; conflicts and failures keep
+    // their old base so they show up again next time
+    let remote_files = index(client::manifest(&connection, remote, false).await?);
+    let local_files = scan(&local)?;
+    connection.close(0u32.into(), b"done");
+
+    let mut files = BTreeMap::new();
+    for (path, l) in &local_files {
+        let base = if unsettled.contains(path) {
+            db.files.get(path).cloned()
+        } else {
+            remote_files.get(path).map(|r| Base {
+                size: l.size,
+                local_modified: l.modified,
+                remote_modified: r.modified,
+            })
+        };
+        if let Some(base) = base {
+            files.insert(path.clone(), base);
+        }
+    }
+    for path in &unsettled {
+        if let Some(base) = db.files.get(path) {
+            files.entry(path.clone()).or_insert_with(|| base.clone());
+        }
+    }
+    db.files = files;
+    save_db(&db_path, &db)?;
+
+    progress::suspend(|| {
+        println!("✅ Sync: {} up, {} down, {} deleted, {} conflict(s)", up, down, deleted, conflicts);
+    });
+    if !errors.is_empty() {
+        anyhow::bail!("{} file(s) failed:\n  {}", errors.len(), errors.join("\n  "));
+    }
+    Ok(())
+}
+
+/// Decide what to do with one path
+async fn plan_one(
+    local: Option<&ManifestEntry>,
+    remote: Option<&ManifestEntry>,
+    base: Option<&Base>,
+    local_path: &Path,
+    remote_path: &str,
+) -> Result<Option<Action>> {
+    let local_changed = changed(local, base.map(|b| (b.size, b.local_modified)));
+    let remote_changed = changed(remote, base.map(|b| (b.size, b.remote_modified)));
+
+    let action = match (local, remote) {
+        (None, None) => base.map(|_| Action::Forget),
+        (Some(_), None) if base.is_none() => Some(Action::Upload),
+        (Some(_), None) if local_changed => Some(Action::Conflict("changed here, deleted on server")),
+        (Some(_), None) => Some(Action::DeleteLocal),
+        (None, Some(_)) if base.is_none() => Some(Action::Download),
+        (None, Some(_)) if remote_changed => Some(Action::Conflict("deleted here, changed on server")),
+        (None, Some(_)) => Some(Action::DeleteRemote),
+        (Some(l), Some(r)) => {
+            if !local_changed && !remote_changed {
+                None
+            } else if identical(l, r, local_path, remote_path).await? {
+                Some(Action::Record)
+            } else {
+                match (local_changed, remote_changed) {
+                    _ if base.is_none() => Some(Action::Conflict("differs on both sides, not synced before")),
+                    (true, false) => Some(Action::Upload),
+                    (false, true) => Some(Action::Download),
+                    _ => Some(Action::Conflict("changed on both sides")),
+                }
+            }
+        }
+    };
+    Ok(action)
+}
+
+/// Differs from the recorded `(size, modified)`; appearing or vanishing counts as a change
+fn changed(entry: Option<&ManifestEntry>, base: Option<(u64, u64)>) -> bool {
+    match (entry, base) {
+        (Some(e), Some((size, modified))) => e.size != size || e.modified != modified,
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+/// Same content on both sides: by hash if the manifest has one, otherwise
+/// assumed for the same size and mtime
+async fn identical(local: &ManifestEntry, remote: &ManifestEntry, local_path: &Path, remote_path: &str) -> Result<bool> {
+    if local.size != remote.size {
+        return Ok(false);
+    }
+    match &remote.hash {
+        Some(remote_hash) => Ok(client::hash_content(local_path, remote_path).await? == *remote_hash),
+        None => Ok(local.modified == remote.modified),
+    }
+}
+
+fn index(entries: Vec<ManifestEntry>) -> BTreeMap<String, ManifestEntry> {
+    entries.into_iter().map(|e| (e.path.clone(), e)).collect()
+}
+
+/// Local files below `dir`, in manifest form (without hashes)
+fn scan(dir: &Path) -> Result<BTreeMap<String, ManifestEntry>> {
+    let mut files = BTreeMap::new();
+    for entry in walkdir::WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy();
+        // Skip interrupted downloads
+        if !entry.file_type().is_file() || (name.starts_with('.') && name.ends_with(".hank-part")) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        let path = entry.path().strip_prefix(dir)?.to_string_lossy().replace('\\', "/");
+        files.insert(path.clone(), ManifestEntry {
+            path,
+            size: metadata.len(),
+            modified: metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs(),
+            hash: None,
+        });
+    }
+    Ok(files)
+}
+
+fn remote_join(remote: &str, rel: &str) -> String {
+    format!("{}/{}", remote.trim_end_matches('/'), rel)
+}
+
+/// One database per (server, local dir, remote dir)
+fn db_path(server: &str, local: &Path, remote: &str) -> Result<PathBuf> {
+    let key = blake3::hash(format!("{}\n{}\n{}", server, local.display(), remote).as_bytes()).to_hex();
+    Ok(config::config_dir()?.join("sync").join(format!("{}.json", &key[..16])))
+}
+
+fn load_db(path: &Path) -> Result<SyncDb> {
+    if !path.exists() {
+        return Ok(SyncDb::default());
+    }
+    let content = std::fs::read_to_string(path)?;
+    serde_json::from_str(&content).with_context(|| format!("Corrupt sync database {:?}", path))
+}
+
+fn save_db(path: &Path, db: &SyncDb) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Write-then-rename so an interrupted save never loses the old state
+    let temp = path.with_extension("json.tmp");
+    std::fs::write(&temp, serde_json::to_string_pretty(db)?)?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(size: u64, modified: u64) -> ManifestEntry {
+        ManifestEntry { path: "f".into(), size, modified, hash: None }
+    }
+
+    #[test]
+    fn notices_changes() {
+        let e = entry(10, 100);
+        assert!(!changed(Some(&e), Some((10, 100))));
+        assert!(changed(Some(&e), Some((11, 100))));
+        assert!(changed(Some(&e), Some((10, 101))));
+        assert!(changed(Some(&e), None));
+        assert!(changed(None, Some((10, 100))));
+        assert!(!changed(None, None));
+    }
+
+    #[tokio::test]
+    async fn compares_without_hashes() {
+        let path = Path::new("unused");
+        assert!(identical(&entry(10, 100), &entry(10, 100), path, "f").await.unwrap());
+        assert!(!identical(&entry(10, 100), &entry(10, 101), path, "f").await.unwrap());
+        assert!(!identical(&entry(10, 100), &entry(11, 100), path, "f").await.unwrap());
+        // Sizes differ, so the local file is not even hashed
+        let hashed = ManifestEntry { hash: Some("00".into()), ..entry(11, 100) };
+        assert!(!identical(&entry(10, 100), &hashed, path, "f").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn plans_every_combination() {
+        let base = Base { size: 10, local_modified: 100, remote_modified: 200 };
+        let local = entry(10, 100);
+        let local_changed = entry(11, 150);
+        let remote = entry(10, 200);
+        let remote_changed = entry(12, 250);
+        let cases = [
+            (None, None, None, None),
+            (None, None, Some(&base), Some(Action::Forget)),
+            (Some(&local), None, None, Some(Action::Upload)),
+            (Some(&local), None, Some(&base), Some(Action::DeleteLocal)),
+            (Some(&local_changed), None, Some(&base), Some(Action::Conflict("changed here, deleted on server"))),
+            (None, Some(&remote), None, Some(Action::Download)),
+            (None, Some(&remote), Some(&base), Some(Action::DeleteRemote)),
+            (None, Some(&remote_changed), Some(&base), Some(Action::Conflict("deleted here, changed on server"))),
+            (Some(&local), Some(&remote), Some(&base), None),
+            (Some(&local_changed), Some(&remote), Some(&base), Some(Action::Upload)),
+            (Some(&local), Some(&remote_changed), Some(&base), Some(Action::Download)),
+            (Some(&local_changed), Some(&remote_changed), Some(&base), Some(Action::Conflict("changed on both sides"))),
+            // The same change on both sides
+            (Some(&local_changed), Some(&local_changed), Some(&base), Some(Action::Record)),
+            (Some(&local), Some(&remote), None, Some(Action::Conflict("differs on both sides, not synced before"))),
+            (Some(&local), Some(&local), None, Some(Action::Record)),
+        ];
+        for (i, (l, r, b, expected)) in cases.into_iter().enumerate() {
+            let action = plan_one(l, r, b, Path::new("unused"), "f").await.unwrap();
+            assert_eq!(action, expected, "case {}", i);
+        }
+    }
+}
@@ -1,14 +1,47 @@
-//! TLS configuration with self-signed certificates
+//! TLS configuration: self-signed or CA-issued certificates, pinning or CA verification
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use quinn::{ClientConfig, ServerConfig};
 use rcgen::{CertifiedKey, generate_simple_self_signed};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-/// Generate a self-signed certificate for testing
-pub fn generate_self_signed() -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
-    let CertifiedKey { cert, key_pair } = generate_simple_self_signed(vec!["localhost".to_string()])?;
+use crate::config::TlsConfig;
+use crate::known_servers::{self, Pin};
+
+/// File names of the persisted self-signed identity inside the config dir
+const SELF_SIGNED_CERT: &str = "server-cert.pem";
+const SELF_SIGNED_KEY: &str = "server-key.pem";
+
+/// Load the server identity: the configured PEM files, or a self-signed
+/// pair persisted in `dir` (generated with `names` as SANs if absent)
+pub fn server_identity(
+    tls: &TlsConfig,
+    dir: &Path,
+    names: &[String],
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => return load_pem(cert, key),
+        (None, None) => {}
+        _ => anyhow::bail!("[tls] needs both cert and key"),
+    }
+    
+    let cert_path = dir.join(SELF_SIGNED_CERT);
+    let key_path = dir.join(SELF_SIGNED_KEY);
+    if cert_path.exists() && key_path.exists() {
+        tracing::info!("🔐 Using certificate {:?}", cert_path);
+        return load_pem(&cert_path, &key_path);
+    }
+    
+    tracing::info!("🔐 Generating self-signed certificate for {:?} in {:?}", names, dir);
+    let CertifiedKey { cert, key_pair } = generate_simple_self_signed(names.to_vec())?;
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(&cert_path, cert.pem())?;
+    write_private(&key_path, key_pair.serialize_pem().as_bytes())?;
     
     let cert_der = CertificateDer::from(cert.der().to_vec());
     let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
@@ -16,12 +49,101 @@ pub fn generate_self_signed() -> Result<(Vec<CertificateDer<'static>>, PrivateKe
     Ok((vec![cert_der], key_der))
 }
 
-/// Create server TLS config
+/// Read a certificate chain and private key from PEM files
+fn load_pem(cert: &Path, key: &Path) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .with_context(|| format!("Cannot read certificate {:?}", cert))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate PEM {:?}", cert))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificate found in {:?}", cert);
+    }
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Cannot read private key {:?}", key))?;
+    Ok((certs, key))
+}
+
+/// Identity of a client that authenticated with a certificate
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    /// Common name, or the first DNS/email SAN if there is no CN
+    pub name: String,
+    pub fingerprint: String,
+}
+
+/// Identity from the verified client certificate of `connection`, if any
+pub fn client_identity(connection: &quinn::Connection) -> Option<ClientIdentity> {
+    let certs = connection.peer_identity()?.downcast::<Vec<CertificateDer<'static>>>().ok()?;
+    let leaf = certs.first()?;
+    let fingerprint = known_servers::fingerprint(leaf);
+    let name = certificate_name(leaf).unwrap_or_else(|| fingerprint.clone());
+    Some(ClientIdentity { name, fingerprint })
+}
+
+fn certificate_name(der: &[u8]) -> Option<String> {
+    use x509_parser::extensions::GeneralName;
+    
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    if let Some(cn) = cert.subject().iter_common_name().next().and_then(|cn| cn.as_str().ok()) {
+        return Some(cn.to_string());
+    }
+    let san = cert.subject_alternative_name().ok()??;
+    san.value.general_names.iter().find_map(|name| match name {
+        GeneralName::DNSName(n) | GeneralName::RFC822Name(n) => Some(n.to_string()),
+        _ => None,
+    })
+}
+
+/// Read all certificates of a PEM CA bundle into a root store
+fn load_ca(ca: &Path) -> Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca).with_context(|| format!("Cannot read CA bundle {:?}", ca))? {
+        let cert = cert.with_context(|| format!("Invalid CA bundle {:?}", ca))?;
+        roots.add(cert).with_context(|| format!("Unusable CA certificate in {:?}", ca))?;
+    }
+    if roots.is_empty() {
+        anyhow::bail!("No certificates found in CA bundle {:?}", ca);
+    }
+    Ok(roots)
+}
+
+/// Write a file readable only by the owner (where supported)
+fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(content)?;
+    Ok(())
+}
+
+/// Create server TLS config; with `client_ca` every client must present a
+/// certificate signed by it
 pub fn server_config(
     certs: Vec<CertificateDer<'static>>,
     key: PrivateKeyDer<'static>,
+    client_ca: Option<&Path>,
 ) -> Result<ServerConfig> {
-    let mut config = ServerConfig::with_single_cert(certs, key)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?;
+    let crypto = match client_ca {
+        Some(ca) => {
+            let roots = load_ca(ca)?;
+            let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .with_context(|| format!("Cannot use client CA {:?}", ca))?;
+            builder.with_client_cert_verifier(verifier).with_single_cert(certs, key)?
+        }
+        None => builder.with_no_client_auth().with_single_cert(certs, key)?,
+    };
+    
+    let mut config = ServerConfig::with_crypto(Arc::new(
+        quinn::crypto::rustls::QuicServerConfig::try_from(crypto)?
+    ));
     
     // Allow 0-RTT for faster reconnects
     Arc::get_mut(&mut config.transport)
@@ -32,12 +154,46 @@ pub fn server_config(
     Ok(config)
 }
 
-/// Create client TLS config (accepts any certificate for self-signed)
-pub fn client_config() -> Result<ClientConfig> {
-    let crypto = rustls::ClientConfig::builder()
+/// How the client treats the server certificate fingerprint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustMode {
+    /// Pin on first use, refuse if the certificate changes later
+    Pin,
+    /// Accept whatever the server presents and replace the pin
+    Accept,
+}
+
+/// Create client TLS config: verify against the CA bundle `tls.ca` if given,
+/// otherwise pin the certificate of `server`. Presents `tls.client_cert` if set.
+pub fn client_config(server: &str, mode: TrustMode, tls: &TlsConfig) -> Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier: Arc<dyn rustls::client::danger::ServerCertVerifier> = match tls.ca.as_deref() {
+        Some(ca) => {
+            let roots = load_ca(ca)?;
+            let inner = rustls::client::WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .with_context(|| format!("Cannot use CA bundle {:?}", ca))?;
+            Arc::new(CaServerVerification { inner, ca: ca.to_path_buf() })
+        }
+        None => Arc::new(PinnedServerVerification {
+            server: server.to_string(),
+            mode,
+            provider: provider.clone(),
+        }),
+    };
+    
+    let builder = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
         .dangerous()
-        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
-        .with_no_client_auth();
+        .with_custom_certificate_verifier(verifier);
+    let crypto = match (&tls.client_cert, &tls.client_key) {
+        (Some(cert), Some(key)) => {
+            let (certs, key) = load_pem(cert, key)?;
+            builder.with_client_auth_cert(certs, key)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => anyhow::bail!("[tls] needs both client_cert and client_key"),
+    };
     
     let config = ClientConfig::new(Arc::new(
         quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?
@@ -46,53 +202,139 @@ pub fn client_config() -> Result<ClientConfig> {
     Ok(config)
 }
 
-/// Skip server certificate verification (for self-signed certs)
-/// TODO: Replace with proper CA verification via hank-ca
+/// Trust-on-first-use verification against `known_servers.json`.
+/// Handshake signatures are still checked against the presented certificate.
 #[derive(Debug)]
-struct SkipServerVerification;
+struct PinnedServerVerification {
+    server: String,
+    mode: TrustMode,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedServerVerification {
+    fn check_pin(&self, fingerprint: String) -> Result<()> {
+        let mut known = known_servers::load()?;
+        match known.servers.get(&self.server) {
+            Some(pin) if pin.fingerprint == fingerprint => return Ok(()),
+            Some(pin) if self.mode == TrustMode::Pin => {
+                eprintln!("⚠️  SERVER CERTIFICATE CHANGED for {}", self.server);
+                eprintln!("    pinned:    {}", pin.fingerprint);
+                eprintln!("    presented: {}", fingerprint);
+                eprintln!("    Someone may be intercepting the connection. If the server identity");
+                eprintln!("    changed legitimately, run: hank-sync trust accept --server {}", self.server);
+                anyhow::bail!("certificate fingerprint of {} does not match pinned value", self.server);
+            }
+            Some(_) => tracing::warn!("🔐 Replacing pinned certificate for {}: {}", self.server, fingerprint),
+            None => tracing::info!("🔐 Pinning certificate for {}: {}", self.server, fingerprint),
+        }
+        known.servers.insert(self.server.clone(), Pin::new(fingerprint));
+        known_servers::save(&known)
+    }
+}
 
-impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
+impl rustls::client::danger::ServerCertVerifier for PinnedServerVerification {
     fn verify_server_cert(
         &self,
-        _end_entity: &CertificateDer<'_>,
+        end_entity: &CertificateDer<'_>,
         _intermediates: &[CertificateDer<'_>],
         _server_name: &rustls::pki_types::ServerName<'_>,
         _ocsp_response: &[u8],
         _now: rustls::pki_types::UnixTime,
     ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        self.check_pin(known_servers::fingerprint(end_entity))
+            .map_err(|e| rustls::Error::General(e.to_string()))?;
         Ok(rustls::client::danger::ServerCertVerified::assertion())
     }
     
     fn verify_tls12_signature(
         &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &rustls::DigitallySignedStruct,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+    
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+    
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// WebPKI verification against a private CA, with readable failure messages
+#[derive(Debug)]
+struct CaServerVerification {
+    inner: Arc<rustls::client::WebPkiServerVerifier>,
+    ca: PathBuf,
+}
+
+impl CaServerVerification {
+    fn explain(&self, err: rustls::Error, server_name: &rustls::pki_types::ServerName<'_>) -> rustls::Error {
+        use rustls::CertificateError as E;
+        let rustls::Error::InvalidCertificate(cert_err) = &err else {
+            return err;
+        };
+        let message = match cert_err {
+            E::Expired | E::ExpiredContext { .. } => {
+                format!("server certificate for {} has expired", server_name.to_str())
+            }
+            E::NotValidYet | E::NotValidYetContext { .. } => {
+                format!("server certificate for {} is not valid yet (check clocks)", server_name.to_str())
+            }
+            E::NotValidForName | E::NotValidForNameContext { .. } => format!(
+                "server certificate is not valid for name {} (set client.server_name to a name in the certificate)",
+                server_name.to_str()
+            ),
+            E::UnknownIssuer => {
+                format!("server certificate is not signed by the CA in {:?}", self.ca)
+            }
+            _ => return err,
+        };
+        rustls::Error::General(message)
+    }
+}
+
+impl rustls::client::danger::ServerCertVerifier for CaServerVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &rustls::pki_types::ServerName<'_>,
+        ocsp_response: &[u8],
+        now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+            .map_err(|e| self.explain(e, server_name))
+    }
+    
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
     ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        self.inner.verify_tls12_signature(message, cert, dss)
     }
     
     fn verify_tls13_signature(
         &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &rustls::DigitallySignedStruct,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
     ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        self.inner.verify_tls13_signature(message, cert, dss)
     }
     
     fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
-        vec![
-            rustls::SignatureScheme::RSA_PKCS1_SHA256,
-            rustls::SignatureScheme::RSA_PKCS1_SHA384,
-            rustls::SignatureScheme::RSA_PKCS1_SHA512,
-            rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
-            rustls::SignatureScheme::ECDSA_NISTP384_SHA384,
-            rustls::SignatureScheme::ECDSA_NISTP521_SHA512,
-            rustls::SignatureScheme::RSA_PSS_SHA256,
-            rustls::SignatureScheme::RSA_PSS_SHA384,
-            rustls::SignatureScheme::RSA_PSS_SHA512,
-            rustls::SignatureScheme::ED25519,
-        ]
+        self.inner.supported_verify_schemes()
     }
 }
@@ -0,0 +1,198 @@
+//! Server-side version store
+//!
+//! With versioning enabled, a file about to be replaced or deleted is first
+//! hard-linked to `.versions/<path>/<timestamp>` below the root. Old versions
+//! are pruned by a retention policy after every new one.
+
+use anyhow::Result;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::protocol::VersionEntry;
+
+/// Name of the store directory in the root (reserved, hidden from clients)
+pub const DIR: &str = ".versions";
+
+/// Version ids are UTC timestamps of when the copy was taken
+const ID_FORMAT: &str = "%Y%m%dT%H%M%S%.6fZ";
+
+/// `[server.versioning]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Retention {
+    /// Always keep this many of the newest versions
+    #[serde(default = "default_keep_last")]
+    pub keep_last: usize,
+    /// Additionally keep the newest version of each of the last N days
+    #[serde(default)]
+    pub keep_daily: u32,
+}
+
+fn default_keep_last() -> usize {
+    10
+}
+
+pub struct VersionStore {
+    dir: PathBuf,
+    /// `None` if versioning is off; existing versions can still be restored
+    retention: Option<Retention>,
+}
+
+impl VersionStore {
+    /// Store below `root` (canonical)
+    pub fn new(root: &Path, retention: Option<Retention>) -> Self {
+        Self { dir: root.join(DIR), retention }
+    }
+
+    /// Keep a copy of the regular file `full` (root-relative `rel`) before it
+    /// is replaced or removed. Returns the new version id, if one was taken.
+    pub fn archive(&self, rel: &str, full: &Path) -> Result<Option<String>> {
+        let Some(retention) = &self.retention else {
+            return Ok(None);
+        };
+        match std::fs::symlink_metadata(full) {
+            Ok(m) if m.is_file() => {}
+            _ => return Ok(None),
+        }
+
+        let dir = self.dir.join(rel);
+        std::fs::create_dir_all(&dir)?;
+        let id = Utc::now().format(ID_FORMAT).to_string();
+        let version = dir.join(&id);
+        // A hard link costs nothing; the replacing rename leaves it untouched
+        if std::fs::hard_link(full, &version).is_err() {
+            std::fs::copy(full, &version)?;
+            let modified = std::fs::metadata(full)?.modified()?;
+            std::fs::File::options().write(true).open(&version)?.set_modified(modified)?;
+        }
+
+        self.prune(&dir, retention)?;
+        Ok(Some(id))
+    }
+
+    /// Versions of `rel`, newest first
+    pub fn list(&self, rel: &str) -> Result<Vec<VersionEntry>> {
+        let dir = self.dir.join(rel);
+        let mut versions = Vec::new();
+        let Ok(read_dir) = std::fs::read_dir(&dir) else {
+            return Ok(versions);
+        };
+        for entry in read_dir {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let id = entry.file_name().to_string_lossy().to_string();
+            if !metadata.is_file() || parse_id(&id).is_none() {
+                continue;
+            }
+            versions.push(VersionEntry {
+                id,
+                size: metadata.len(),
+                modified: metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs(),
+            });
+        }
+        versions.sort_by(|a, b| b.id.cmp(&a.id));
+        Ok(versions)
+    }
+
+    /// Location of version `id` of `rel`
+    pub fn path_of(&self, rel: &str, id: &str) -> Result<PathBuf> {
+        let mut components = Path::new(id).components();
+        if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+            || parse_id(id).is_none()
+        {
+            anyhow::bail!("Invalid version id: {}", id);
+        }
+        let path = self.dir.join(rel).join(id);
+        if !path.is_file() {
+            anyhow::bail!("No version {} of {}", id, rel);
+        }
+        Ok(path)
+    }
+
+    /// Drop versions in `dir` that the retention policy no longer covers
+    fn prune(&self, dir: &Path, retention: &Retention) -> Result<()> {
+        let ids: Vec<String> = std::fs::read_dir(dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect();
+        for id in expired(&ids, Utc::now().naive_utc(), retention) {
+            if let Err(e) = std::fs::remove_file(dir.join(id)) {
+                tracing::warn!("Could not prune version {:?}: {}", dir.join(id), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Which of the version `ids` the retention policy drops at `now`
+fn expired<'a>(ids: &'a [String], now: NaiveDateTime, retention: &Retention) -> Vec<&'a str> {
+    let mut ids: Vec<&str> = ids.iter().map(String::as_str).filter(|id| parse_id(id).is_some()).collect();
+    ids.sort_by(|a, b| b.cmp(a));
+
+    let mut days_kept = HashSet::new();
+    let mut expired = Vec::new();
+    for (i, id) in ids.into_iter().enumerate() {
+        let Some(taken) = parse_id(id) else { continue };
+        let recent = (now - taken).num_days() < retention.keep_daily as i64;
+        // Newest first, so the first one seen per day is that day's last
+        let daily = recent && days_kept.insert(taken.date());
+        if i >= retention.keep_last && !daily {
+            expired.push(id);
+        }
+    }
+    expired
+}
+
+fn parse_id(id: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(id, ID_FORMAT).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(s: &str) -> String {
+        format!("{}.000000Z", s)
+    }
+
+    fn now() -> NaiveDateTime {
+        parse_id(&id("20260110T120000")).unwrap()
+    }
+
+    #[test]
+    fn keeps_the_newest() {
+        let ids: Vec<String> = ["20260110T100000", "20260110T110000", "20260109T100000", "20260101T000000"]
+            .iter().map(|s| id(s)).collect();
+        let retention = Retention { keep_last: 2, keep_daily: 0 };
+        let mut expired = expired(&ids, now(), &retention);
+        expired.sort();
+        assert_eq!(expired, [id("20260101T000000"), id("20260109T100000")]);
+
+        let retention = Retention { keep_last: 10, keep_daily: 0 };
+        assert!(super::expired(&ids, now(), &retention).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_last_of_each_recent_day() {
+        let ids: Vec<String> = [
+            "20260110T110000", "20260110T100000",
+            "20260109T230000", "20260109T080000",
+            "20260108T120000",
+            "20260101T000000",
+        ].iter().map(|s| id(s)).collect();
+        let retention = Retention { keep_last: 1, keep_daily: 3 };
+        let mut expired = expired(&ids, now(), &retention);
+        expired.sort();
+        // 01-01 is beyond the three days, the others are not their day's last
+        assert_eq!(expired, [id("20260101T000000"), id("20260109T080000"), id("20260110T100000")]);
+    }
+
+    #[test]
+    fn ignores_foreign_files() {
+        let ids = vec!["notes.txt".to_string(), id("20260101T000000")];
+        let retention = Retention { keep_last: 0, keep_daily: 0 };
+        assert_eq!(expired(&ids, now(), &retention), [id("20260101T000000")]);
+    }
+}